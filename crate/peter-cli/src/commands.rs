@@ -0,0 +1,1195 @@
+//! Peter's slash commands. Each command is declared once in [`COMMANDS`], which is used both to register the commands in each guild and to dispatch interactions.
+
+use {
+    std::{
+        collections::hash_map,
+        future::Future,
+        iter,
+        pin::Pin,
+    },
+    chrono::{
+        TimeDelta,
+        prelude::*,
+    },
+    itertools::Itertools as _,
+    rand::prelude::*,
+    serenity::{
+        all::{
+            CreateAllowedMentions,
+            CreateAutocompleteResponse,
+            CreateCommand,
+            CreateCommandOption,
+            CreateInteractionResponse,
+            CreateInteractionResponseFollowup,
+            CreateInteractionResponseMessage,
+            EditInteractionResponse,
+            EditMember,
+        },
+        model::prelude::*,
+        prelude::*,
+        utils::MessageBuilder,
+    },
+    serenity_utils::handler::voice_state::VoiceStates,
+    peter::{
+        Error,
+        config::{
+            Config,
+            GuildConfig,
+        },
+        guests,
+        poll,
+        quiz::{
+            self,
+            Quiz,
+            Teams,
+        },
+        roles,
+        stream::{
+            self,
+            StreamProvider,
+        },
+        twitch::Twitch,
+        user_list,
+        werewolf,
+        youtube::YouTube,
+    },
+};
+
+/// The maximum number of entries shown by `/history`, to stay within Discord's message length limit.
+const HISTORY_LEN: usize = 10;
+
+/// A slash command along with everything needed to register and handle it.
+pub(crate) struct SlashCommand {
+    name: &'static str,
+    /// Whether the command is available in the given guild.
+    scope: fn(&Config, GuildId) -> bool,
+    /// Adds the description and options to the command.
+    build: fn(&Config, GuildId, CreateCommand) -> CreateCommand,
+    handler: for<'a> fn(&'a Context, &'a CommandInteraction) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'a>>,
+    /// Responds to autocomplete requests for the command's options, if any of them have autocomplete enabled.
+    autocomplete: Option<for<'a> fn(&'a Context, &'a CommandInteraction) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'a>>>,
+}
+
+const COMMANDS: &[SlashCommand] = &[
+    SlashCommand {
+        name: "answer",
+        scope: has_teams,
+        build: |_, _, command| command
+            .description("Eine Antwort für dein Team in der aktuellen Quizrunde abgeben")
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::String,
+                "answer",
+                "die Antwort",
+            ).required(true)),
+        handler: answer,
+        autocomplete: None,
+    },
+    SlashCommand {
+        name: "buzz",
+        scope: has_teams,
+        build: |_, _, command| command
+            .description("Für dein Team in der aktuellen Quizrunde buzzern"),
+        handler: buzz,
+        autocomplete: None,
+    },
+    SlashCommand {
+        name: "day",
+        scope: everywhere,
+        build: |_, _, command| command,
+        handler: day,
+        autocomplete: None,
+    },
+    SlashCommand {
+        name: "guest",
+        scope: |config, guild_id| config.database && config.guilds.get(&guild_id).is_some_and(|guild_config| guild_config.guest.is_some()),
+        build: |_, _, command| command
+            .description("Jemandem vorübergehend die Gastrolle geben (nur für Admins)")
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::User,
+                "user",
+                "die Person",
+            ).required(true))
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::Integer,
+                "days",
+                "nach wie vielen Tagen die Gastrolle wieder entfernt wird",
+            )
+                .required(true)
+                .min_int_value(1)
+                .max_int_value(guests::MAX_DAYS)
+            ),
+        handler: guest,
+        autocomplete: None,
+    },
+    SlashCommand {
+        name: "history",
+        scope: |config, guild_id| config.database && main_guild(config, guild_id),
+        build: |_, _, command| command
+            .description("Die letzten Änderungen an Nickname und Rollen einer Person anzeigen (nur für Admins)")
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::User,
+                "user",
+                "die Person",
+            ).required(true)),
+        handler: history,
+        autocomplete: None,
+    },
+    SlashCommand {
+        name: "iam",
+        scope: main_guild,
+        build: |_, _, command| command
+            .description("Dir eine selbstzuweisbare Rolle zuweisen")
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::String,
+                "role",
+                "die Rolle, die du haben möchtest",
+            )
+                .required(true)
+                .set_autocomplete(true)
+            ),
+        handler: iam,
+        autocomplete: Some(iam_autocomplete),
+    },
+    SlashCommand {
+        name: "iamn",
+        scope: main_guild,
+        build: |_, _, command| command
+            .description("Eine selbstzuweisbare Rolle von dir entfernen")
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::String,
+                "role",
+                "die Rolle, die du loswerden möchtest",
+            )
+                .required(true)
+                .set_autocomplete(true)
+            ),
+        handler: iamn,
+        autocomplete: Some(iamn_autocomplete),
+    },
+    SlashCommand {
+        name: "in",
+        scope: everywhere,
+        build: |_, _, command| command,
+        handler: r#in,
+        autocomplete: None,
+    },
+    SlashCommand {
+        name: "night",
+        scope: everywhere,
+        build: |_, _, command| command,
+        handler: night,
+        autocomplete: None,
+    },
+    SlashCommand {
+        name: "out",
+        scope: everywhere,
+        build: |_, _, command| command,
+        handler: out,
+        autocomplete: None,
+    },
+    SlashCommand {
+        name: "ping",
+        scope: main_guild,
+        build: |_, _, command| command
+            .description("Testen, ob Peter online ist"),
+        handler: ping,
+        autocomplete: None,
+    },
+    SlashCommand {
+        name: "poll",
+        scope: with_database,
+        build: |_, _, command| command
+            .description("Eine Umfrage starten")
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::String,
+                "question",
+                "die Frage",
+            )
+                .required(true)
+                .max_length(poll::MAX_QUESTION_LEN)
+            )
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::String,
+                "options",
+                "die Antwortmöglichkeiten, durch Semikolons getrennt",
+            )
+                .required(true)
+                .max_length(poll::MAX_OPTIONS_LEN)
+            )
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::Boolean,
+                "multi",
+                "ob mehrere Antworten gewählt werden können (Standard: nein)",
+            ))
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::Boolean,
+                "anonymous",
+                "ob nur die Anzahl der Stimmen veröffentlicht wird, erfordert Buttons (Standard: nein)",
+            ))
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::Boolean,
+                "buttons",
+                "ob per Button statt per Reaktion abgestimmt wird (Standard: nein)",
+            ))
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::Integer,
+                "duration",
+                "nach wie vielen Minuten die Umfrage automatisch endet (Standard: nie)",
+            )
+                .min_int_value(1)
+                .max_int_value(poll::MAX_DURATION_MINUTES)
+            ),
+        handler: poll,
+        autocomplete: None,
+    },
+    SlashCommand {
+        name: "promote",
+        scope: |config, guild_id| config.database && config.guilds.get(&guild_id).is_some_and(|guild_config| guild_config.guest.is_some() && guild_config.mensch.is_some()),
+        build: |_, _, command| command
+            .description("Einen Gast zum Mensch befördern (nur für Admins)")
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::User,
+                "user",
+                "die Person",
+            ).required(true)),
+        handler: promote,
+        autocomplete: None,
+    },
+    SlashCommand {
+        name: "quiz",
+        scope: has_teams,
+        build: |config, guild_id, command| command
+            .description("Ein Quiz moderieren (nur für den Quizmaster)")
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "start",
+                "Ein Quiz in diesem Channel starten",
+            ))
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "round",
+                "Eine neue Runde eröffnen",
+            ).add_sub_option(CreateCommandOption::new(
+                CommandOptionType::String,
+                "question",
+                "die Frage, falls sie im Channel angezeigt werden soll",
+            )))
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "close",
+                "Die aktuelle Runde beenden und die Antworten anzeigen",
+            ))
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "points",
+                "Einem Team Punkte geben oder abziehen",
+            )
+                .add_sub_option(CreateCommandOption::new(
+                    CommandOptionType::Integer,
+                    "team",
+                    "die Teamnummer",
+                )
+                    .required(true)
+                    .min_int_value(1)
+                    .max_int_value(quiz::num_teams(config, guild_id) as u64)
+                )
+                .add_sub_option(CreateCommandOption::new(
+                    CommandOptionType::Integer,
+                    "points",
+                    "die Anzahl der Punkte, negativ zum Abziehen",
+                ).required(true))
+            )
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "end",
+                "Das Quiz beenden und den Endstand speichern",
+            )),
+        handler: quiz,
+        autocomplete: None,
+    },
+    SlashCommand {
+        name: "reload-config",
+        scope: main_guild,
+        build: |_, _, command| command
+            .description("Die Konfigurationsdatei neu laden (nur für Admins)"),
+        handler: reload_config,
+        autocomplete: None,
+    },
+    SlashCommand {
+        name: "reset-quiz",
+        scope: has_teams,
+        build: |_, _, command| command
+            .description("Die Rollen und Nicknames für Quizmaster und Teams aufräumen"),
+        handler: reset_quiz,
+        autocomplete: None,
+    },
+    SlashCommand {
+        name: "roles",
+        scope: main_guild,
+        build: |_, _, command| command
+            .description("Nachrichten zum Auswählen der selbstzuweisbaren Rollen posten (nur für Admins)"),
+        handler: roles,
+        autocomplete: None,
+    },
+    SlashCommand {
+        name: "selfassign",
+        scope: main_guild,
+        build: |_, _, command| command
+            .description("Selbstzuweisbare Rollen verwalten (nur für Admins)")
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "add",
+                "Eine Rolle selbstzuweisbar machen",
+            ).add_sub_option(CreateCommandOption::new(
+                CommandOptionType::Role,
+                "role",
+                "die Rolle",
+            ).required(true)))
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "remove",
+                "Eine Rolle nicht mehr selbstzuweisbar machen",
+            ).add_sub_option(CreateCommandOption::new(
+                CommandOptionType::Role,
+                "role",
+                "die Rolle",
+            ).required(true))),
+        handler: selfassign,
+        autocomplete: None,
+    },
+    SlashCommand {
+        name: "stream",
+        scope: |config, guild_id| config.database && main_guild(config, guild_id),
+        build: |config, _, command| {
+            let platform = || {
+                let option = CreateCommandOption::new(
+                    CommandOptionType::String,
+                    "platform",
+                    "die Streaming-Plattform",
+                )
+                    .required(true)
+                    .add_string_choice(Twitch::NAME, Twitch::PLATFORM);
+                // YouTube is only offered if it's configured
+                if config.youtube.is_some() { option.add_string_choice(YouTube::NAME, YouTube::PLATFORM) } else { option }
+            };
+            command
+                .description("Deinen Streaming-Account verknüpfen, damit deine Streams angekündigt werden")
+                .add_option(CreateCommandOption::new(
+                    CommandOptionType::SubCommand,
+                    "link",
+                    "Deinen Streaming-Account verknüpfen",
+                )
+                    .add_sub_option(platform())
+                    .add_sub_option(CreateCommandOption::new(
+                        CommandOptionType::String,
+                        "account",
+                        "dein Benutzername oder Kanal auf der Plattform",
+                    ).required(true))
+                )
+                .add_option(CreateCommandOption::new(
+                    CommandOptionType::SubCommand,
+                    "unlink",
+                    "Die Verknüpfung mit deinem Streaming-Account entfernen",
+                ).add_sub_option(platform()))
+                .add_option(CreateCommandOption::new(
+                    CommandOptionType::SubCommand,
+                    "announce",
+                    "Einstellen, ob deine Streams angekündigt werden",
+                )
+                    .add_sub_option(platform())
+                    .add_sub_option(CreateCommandOption::new(
+                        CommandOptionType::Boolean,
+                        "enabled",
+                        "ob deine Streams angekündigt werden",
+                    ).required(true))
+                )
+        },
+        handler: stream,
+        autocomplete: None,
+    },
+    SlashCommand {
+        name: "team",
+        scope: has_teams,
+        build: |config, guild_id, command| command
+            .description("In ein Team wechseln, z.B. für ein Quiz")
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::Integer,
+                "team",
+                "Die Teamnummer",
+            )
+                .required(true)
+                .min_int_value(1)
+                .max_int_value(quiz::num_teams(config, guild_id) as u64)
+            ),
+        handler: team,
+        autocomplete: None,
+    },
+    SlashCommand {
+        name: "teams",
+        scope: has_teams,
+        build: |config, guild_id, command| command
+            .description("Teams verwalten (nur für den Quizmaster)")
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "shuffle",
+                "Alle im Voice-Channel zufällig in gleich große Teams einteilen",
+            ).add_sub_option(CreateCommandOption::new(
+                CommandOptionType::Integer,
+                "count",
+                "die Anzahl der Teams",
+            )
+                .required(true)
+                .min_int_value(1)
+                .max_int_value(quiz::num_teams(config, guild_id) as u64)
+            )),
+        handler: teams,
+        autocomplete: None,
+    },
+];
+
+/// A message component handler, responsible for all components whose custom ID starts with the given prefix.
+struct Component {
+    prefix: &'static str,
+    handler: for<'a> fn(&'a Context, &'a ComponentInteraction) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'a>>,
+}
+
+const COMPONENTS: &[Component] = &[
+    Component {
+        prefix: "poll:",
+        handler: |ctx, interaction| Box::pin(poll::handle_component(ctx, interaction)),
+    },
+    Component {
+        prefix: "role:",
+        handler: |ctx, interaction| Box::pin(roles::handle_component(ctx, interaction)),
+    },
+];
+
+fn everywhere(_: &Config, _: GuildId) -> bool { true }
+fn main_guild(config: &Config, guild_id: GuildId) -> bool { guild_id == config.main_guild }
+fn with_database(config: &Config, _: GuildId) -> bool { config.database }
+// teams store nicknames and created roles in the database
+fn has_teams(config: &Config, guild_id: GuildId) -> bool { config.database && quiz::num_teams(config, guild_id) > 0 }
+
+/// Registers all commands available in the given guild, replacing any previously registered ones.
+pub(crate) async fn register(ctx: &Context, guild_id: GuildId) -> serenity::Result<()> {
+    let commands = {
+        let data = ctx.data.read().await;
+        let config = data.get::<Config>().expect("missing config");
+        COMMANDS.iter()
+            .filter(|command| (command.scope)(config, guild_id))
+            .map(|command| (command.build)(config, guild_id, CreateCommand::new(command.name)
+                .kind(CommandType::ChatInput)
+                .dm_permission(false)
+            ))
+            .collect()
+    };
+    guild_id.set_commands(ctx, commands).await?;
+    Ok(())
+}
+
+/// Runs the handler of the slash command with the interaction's name.
+pub(crate) async fn handle(ctx: &Context, interaction: &CommandInteraction) -> Result<(), Error> {
+    let command = COMMANDS.iter().find(|command| command.name == interaction.data.name).ok_or_else(|| Error::UnknownCommand(interaction.data.name.clone()))?;
+    (command.handler)(ctx, interaction).await
+}
+
+/// Runs the autocomplete handler of the slash command with the interaction's name.
+pub(crate) async fn handle_autocomplete(ctx: &Context, interaction: &CommandInteraction) -> Result<(), Error> {
+    let command = COMMANDS.iter().find(|command| command.name == interaction.data.name).ok_or_else(|| Error::UnknownCommand(interaction.data.name.clone()))?;
+    let autocomplete = command.autocomplete.ok_or_else(|| Error::UnknownCommand(interaction.data.name.clone()))?;
+    autocomplete(ctx, interaction).await
+}
+
+/// Runs the handler responsible for the message component with the interaction's custom ID.
+pub(crate) async fn handle_component(ctx: &Context, interaction: &ComponentInteraction) -> Result<(), Error> {
+    let component = COMPONENTS.iter().find(|component| interaction.data.custom_id.starts_with(component.prefix)).ok_or_else(|| Error::UnknownComponent(interaction.data.custom_id.clone()))?;
+    (component.handler)(ctx, interaction).await
+}
+
+/// Tells the user that their interaction failed.
+///
+/// The error itself is reported to the configured error notifiers by returning it from the interaction handler. It isn't shown to the user since it may contain internal details.
+pub(crate) async fn reply_error(ctx: &Context, interaction: &Interaction) -> serenity::Result<()> {
+    let content = "beim Ausführen ist ein Fehler aufgetreten, er wurde gemeldet";
+    let response = CreateInteractionResponse::Message(CreateInteractionResponseMessage::new()
+        .ephemeral(true)
+        .content(content)
+    );
+    let followup = CreateInteractionResponseFollowup::new()
+        .ephemeral(true)
+        .content(content);
+    // the handler may have failed after already responding, in which case a followup message is sent instead
+    match interaction {
+        Interaction::Command(interaction) => if interaction.create_response(ctx, response).await.is_err() {
+            interaction.create_followup(ctx, followup).await?;
+        },
+        Interaction::Component(interaction) => if interaction.create_response(ctx, response).await.is_err() {
+            interaction.create_followup(ctx, followup).await?;
+        },
+        _ => {}
+    }
+    Ok(())
+}
+
+fn member(interaction: &CommandInteraction) -> Result<&Member, Error> {
+    interaction.member.as_deref().ok_or(Error::NotInGuild)
+}
+
+/// Returns the name and options of the subcommand that was called.
+fn subcommand(interaction: &CommandInteraction) -> Result<(&str, &[CommandDataOption]), Error> {
+    match interaction.data.options.first() {
+        Some(CommandDataOption { name, value: CommandDataOptionValue::SubCommand(options), .. }) => Ok((name, options)),
+        _ => Err(Error::CommandOption("subcommand")),
+    }
+}
+
+/// Returns the value of the slash command option with the given name, or `None` if it was omitted.
+fn option<'a, T>(options: &'a [CommandDataOption], name: &'static str, extract: impl FnOnce(&'a CommandDataOptionValue) -> Option<T>) -> Result<Option<T>, Error> {
+    options.iter()
+        .find(|option| option.name == name)
+        .map(|option| extract(&option.value).ok_or(Error::CommandOption(name)))
+        .transpose()
+}
+
+fn required_option<'a, T>(options: &'a [CommandDataOption], name: &'static str, extract: impl FnOnce(&'a CommandDataOptionValue) -> Option<T>) -> Result<T, Error> {
+    option(options, name, extract)?.ok_or(Error::CommandOption(name))
+}
+
+/// Whether the member who used the command has the role which `role` selects from their guild's config.
+async fn has_role(ctx: &Context, interaction: &CommandInteraction, role: fn(&GuildConfig) -> Option<RoleId>) -> Result<bool, Error> {
+    let member = member(interaction)?;
+    Ok(ctx.data.read().await.get::<Config>().expect("missing config").has_role(member, role))
+}
+
+async fn reply(ctx: &Context, interaction: &CommandInteraction, ephemeral: bool, content: impl Into<String>) -> serenity::Result<()> {
+    interaction.create_response(ctx, CreateInteractionResponse::Message(CreateInteractionResponseMessage::new()
+        .ephemeral(ephemeral)
+        .content(content)
+    )).await
+}
+
+fn answer<'a>(ctx: &'a Context, interaction: &'a CommandInteraction) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'a>> {
+    Box::pin(async move {
+        let guild_id = interaction.guild_id.ok_or(Error::NotInGuild)?;
+        let teams = Teams::get(ctx, guild_id).await?.ok_or(Error::NoTeams)?;
+        let Some(team) = teams.team_of(member(interaction)?) else {
+            reply(ctx, interaction, true, "du bist in keinem Team").await?;
+            return Ok(())
+        };
+        let answer = required_option(&interaction.data.options, "answer", CommandDataOptionValue::as_str)?.to_owned();
+        let submitted = ctx.data.write().await.get_mut::<Quiz>().expect("missing quiz state").get_mut(&guild_id).map_or(false, |quiz| quiz.submit_answer(team, answer));
+        reply(ctx, interaction, true, if submitted {
+            format!("Antwort für Team {} gespeichert", team + 1)
+        } else {
+            format!("aktuell läuft keine Quizrunde")
+        }).await?;
+        Ok(())
+    })
+}
+
+fn buzz<'a>(ctx: &'a Context, interaction: &'a CommandInteraction) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'a>> {
+    Box::pin(async move {
+        let guild_id = interaction.guild_id.ok_or(Error::NotInGuild)?;
+        let teams = Teams::get(ctx, guild_id).await?.ok_or(Error::NoTeams)?;
+        let Some(team) = teams.team_of(member(interaction)?) else {
+            reply(ctx, interaction, true, "du bist in keinem Team").await?;
+            return Ok(())
+        };
+        let position = ctx.data.write().await.get_mut::<Quiz>().expect("missing quiz state").get_mut(&guild_id).and_then(|quiz| quiz.buzz(team));
+        if let Some(position) = position {
+            reply(ctx, interaction, false, format!("🔔 Team {} hat gebuzzert (Platz {position})", team + 1)).await?;
+        } else {
+            reply(ctx, interaction, true, "aktuell läuft keine Quizrunde oder dein Team hat schon gebuzzert").await?;
+        }
+        Ok(())
+    })
+}
+
+fn day<'a>(ctx: &'a Context, interaction: &'a CommandInteraction) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'a>> {
+    Box::pin(async move {
+        match werewolf::channel_check(ctx, interaction).await {
+            Ok(guild) => {
+                let data = ctx.data.read().await;
+                let conf = *data.get::<Config>().expect("missing config").werewolf.get(&guild).expect("unconfigured guild but check passed");
+                if let Some(voice_channel) = conf.voice_channel {
+                    let voice_states = data.get::<VoiceStates>().expect("missing voice states map");
+                    let VoiceStates(ref chan_map) = voice_states;
+                    if let Some((_, users)) = chan_map.get(&voice_channel) {
+                        for user in users {
+                            guild.edit_member(ctx, user, EditMember::default().mute(false)).await?;
+                        }
+                    }
+                }
+            }
+            Err(response) => reply(ctx, interaction, true, response).await?,
+        }
+        Ok(())
+    })
+}
+
+fn guest<'a>(ctx: &'a Context, interaction: &'a CommandInteraction) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'a>> {
+    Box::pin(async move {
+        let guild_id = interaction.guild_id.ok_or(Error::NotInGuild)?;
+        if !has_role(ctx, interaction, |guild| guild.admin).await? {
+            reply(ctx, interaction, true, "nur Admins können Gastzugänge vergeben").await?;
+            return Ok(())
+        }
+        let user_id = required_option(&interaction.data.options, "user", CommandDataOptionValue::as_user_id)?;
+        let days = required_option(&interaction.data.options, "days", CommandDataOptionValue::as_i64)?;
+        let Some(expires_at) = TimeDelta::try_days(days).and_then(|duration| Utc::now().checked_add_signed(duration)) else {
+            reply(ctx, interaction, true, "diese Dauer ist zu lang").await?;
+            return Ok(())
+        };
+        let pool = peter::database(&*ctx.data.read().await)?;
+        guests::grant(ctx, &pool, guild_id, user_id, interaction.user.id, expires_at).await?;
+        reply(ctx, interaction, true, MessageBuilder::default()
+            .mention(&user_id)
+            .push(format!(" ist jetzt Gast bis <t:{}:f>", expires_at.timestamp()))
+            .build()
+        ).await?;
+        Ok(())
+    })
+}
+
+fn history<'a>(ctx: &'a Context, interaction: &'a CommandInteraction) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'a>> {
+    Box::pin(async move {
+        if !has_role(ctx, interaction, |guild| guild.admin).await? {
+            reply(ctx, interaction, true, "nur Admins können die Historie ansehen").await?;
+            return Ok(())
+        }
+        let user_id = required_option(&interaction.data.options, "user", CommandDataOptionValue::as_user_id)?;
+        let pool = peter::database(&*ctx.data.read().await)?;
+        let history = user_list::history(&pool, user_id).await?;
+        if history.is_empty() {
+            reply(ctx, interaction, true, "für diese Person gibt es keine Einträge").await?;
+            return Ok(())
+        }
+        let mut builder = MessageBuilder::default();
+        for entry in history.iter().rev().take(HISTORY_LEN).rev() {
+            builder.push(format!("<t:{}:d> ", entry.at.timestamp()));
+            if entry.member {
+                if let Some(ref nick) = entry.nick {
+                    builder.push_bold_safe(nick).push(" (").push_safe(&entry.username).push(")");
+                } else {
+                    builder.push_bold_safe(&entry.username);
+                }
+                builder.push(": ");
+                if entry.roles.is_empty() {
+                    builder.push("keine Rollen");
+                }
+                for (idx, role) in entry.roles.iter().enumerate() {
+                    if idx > 0 { builder.push(", "); }
+                    builder.mention(role);
+                }
+            } else {
+                builder.push("Server verlassen");
+            }
+            builder.push_line("");
+        }
+        interaction.create_response(ctx, CreateInteractionResponse::Message(CreateInteractionResponseMessage::new()
+            .ephemeral(true)
+            .content(builder.build())
+            .allowed_mentions(CreateAllowedMentions::new())
+        )).await?;
+        Ok(())
+    })
+}
+
+fn iam<'a>(ctx: &'a Context, interaction: &'a CommandInteraction) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'a>> {
+    Box::pin(async move {
+        let member = member(interaction)?;
+        let text = required_option(&interaction.data.options, "role", CommandDataOptionValue::as_str)?;
+        let Some(role_id) = roles::find(ctx, member.guild_id, text).await? else {
+            reply(ctx, interaction, true, "unbekannte Rolle, wähle eine der vorgeschlagenen Rollen aus").await?;
+            return Ok(())
+        };
+        let response = roles::add(ctx, member, role_id).await?.err().unwrap_or("✅");
+        reply(ctx, interaction, true, response).await?;
+        Ok(())
+    })
+}
+
+fn iamn<'a>(ctx: &'a Context, interaction: &'a CommandInteraction) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'a>> {
+    Box::pin(async move {
+        let member = member(interaction)?;
+        let text = required_option(&interaction.data.options, "role", CommandDataOptionValue::as_str)?;
+        let Some(role_id) = roles::find(ctx, member.guild_id, text).await? else {
+            reply(ctx, interaction, true, "unbekannte Rolle, wähle eine der vorgeschlagenen Rollen aus").await?;
+            return Ok(())
+        };
+        let response = roles::remove(ctx, member, role_id).await?.err().unwrap_or("✅");
+        reply(ctx, interaction, true, response).await?;
+        Ok(())
+    })
+}
+
+fn iam_autocomplete<'a>(ctx: &'a Context, interaction: &'a CommandInteraction) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'a>> {
+    Box::pin(role_autocomplete(ctx, interaction, false))
+}
+
+fn iamn_autocomplete<'a>(ctx: &'a Context, interaction: &'a CommandInteraction) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'a>> {
+    Box::pin(role_autocomplete(ctx, interaction, true))
+}
+
+/// Suggests the self-assignable roles which the member has (`has = true`) or doesn't have (`has = false`).
+async fn role_autocomplete(ctx: &Context, interaction: &CommandInteraction, has: bool) -> Result<(), Error> {
+    let guild_id = interaction.guild_id.ok_or(Error::NotInGuild)?;
+    let partial = interaction.data.autocomplete().map_or("", |option| option.value);
+    let choices = roles::autocomplete(ctx, guild_id, member(interaction)?, Some(has), partial).await?;
+    interaction.create_response(ctx, CreateInteractionResponse::Autocomplete(choices.into_iter().fold(CreateAutocompleteResponse::new(), |response, (name, value)| response.add_string_choice(name, value)))).await?;
+    Ok(())
+}
+
+fn r#in<'a>(ctx: &'a Context, interaction: &'a CommandInteraction) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'a>> {
+    Box::pin(async move {
+        match werewolf::channel_check(ctx, interaction).await {
+            Ok(guild) => {
+                {
+                    let mut data = ctx.data.write().await;
+                    let conf = *data.get::<Config>().expect("missing config").werewolf.get(&guild).expect("unconfigured guild but check passed");
+                    let state = data.get_mut::<werewolf::GameState>().expect("missing Werewolf game state");
+                    if state.iter().any(|(&iter_guild, iter_state)| iter_guild != guild && iter_state.state.secret_ids().map_or(false, |secret_ids| secret_ids.contains(&interaction.user.id))) {
+                        reply(ctx, interaction, true, "du bist schon in einem Spiel auf einem anderen Server").await?;
+                        return Ok(())
+                    }
+                    let state = state.entry(guild).or_insert_with(|| werewolf::GameState::new(guild, conf));
+                    if let werewolf::State::Complete(_) = state.state {
+                        state.state = werewolf::State::default();
+                    }
+                    if let werewolf::State::Signups(ref mut signups) = state.state {
+                        // sign up for game
+                        if !signups.sign_up(interaction.user.id) {
+                            reply(ctx, interaction, true, "du bist schon angemeldet").await?;
+                            return Ok(())
+                        }
+                        // add DISCUSSION_ROLE
+                        let roles = iter::once(conf.role).chain(member(interaction)?.roles.iter().copied());
+                        guild.edit_member(&ctx, interaction.user.id, EditMember::default().roles(roles)).await?;
+                        reply(ctx, interaction, false, "✅").await?;
+                    } else {
+                        reply(ctx, interaction, true, "bitte warte, bis das aktuelle Spiel vorbei ist").await?;
+                        return Ok(())
+                    }
+                }
+                werewolf::continue_game(&ctx, guild).await?;
+            }
+            Err(response) => reply(ctx, interaction, true, response).await?,
+        }
+        Ok(())
+    })
+}
+
+fn night<'a>(ctx: &'a Context, interaction: &'a CommandInteraction) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'a>> {
+    Box::pin(async move {
+        match werewolf::channel_check(ctx, interaction).await {
+            Ok(guild) => {
+                let data = ctx.data.read().await;
+                let conf = *data.get::<Config>().expect("missing config").werewolf.get(&guild).expect("unconfigured guild but check passed");
+                if let Some(voice_channel) = conf.voice_channel {
+                    let voice_states = data.get::<VoiceStates>().expect("missing voice states map");
+                    let VoiceStates(ref chan_map) = voice_states;
+                    if let Some((_, users)) = chan_map.get(&voice_channel) {
+                        for user in users {
+                            if *user != interaction.user {
+                                guild.edit_member(ctx, user, EditMember::default().mute(true)).await?;
+                            }
+                        }
+                    }
+                }
+            }
+            Err(response) => reply(ctx, interaction, true, response).await?,
+        }
+        Ok(())
+    })
+}
+
+fn out<'a>(ctx: &'a Context, interaction: &'a CommandInteraction) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'a>> {
+    Box::pin(async move {
+        match werewolf::channel_check(ctx, interaction).await {
+            Ok(guild) => {
+                {
+                    let mut data = ctx.data.write().await;
+                    let conf = *data.get::<Config>().expect("missing config").werewolf.get(&guild).expect("unconfigured guild but check passed");
+                    let state = data.get_mut::<werewolf::GameState>().expect("missing Werewolf game state").entry(guild).or_insert_with(|| werewolf::GameState::new(guild, conf));
+                    if let werewolf::State::Complete(_) = state.state {
+                        state.state = werewolf::State::default();
+                    }
+                    if let werewolf::State::Signups(ref mut signups) = state.state {
+                        if !signups.remove_player(&interaction.user.id) {
+                            reply(ctx, interaction, true, "du warst nicht angemeldet").await?;
+                            return Ok(())
+                        }
+                        // remove DISCUSSION_ROLE
+                        let roles = member(interaction)?.roles.iter().copied().filter(|&role| role != conf.role);
+                        guild.edit_member(&ctx, interaction.user.id, EditMember::default().roles(roles)).await?;
+                        reply(ctx, interaction, false, "✅").await?;
+                    } else {
+                        reply(ctx, interaction, true, "bitte warte, bis das aktuelle Spiel vorbei ist").await?; //TODO implement forfeiting
+                        return Ok(())
+                    }
+                }
+                werewolf::continue_game(&ctx, guild).await?;
+            }
+            Err(response) => reply(ctx, interaction, true, response).await?,
+        }
+        Ok(())
+    })
+}
+
+fn ping<'a>(ctx: &'a Context, interaction: &'a CommandInteraction) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'a>> {
+    Box::pin(async move {
+        let response = {
+            let mut rng = thread_rng();
+            if rng.gen_bool(0.01) {
+                format!("BWO{}{}G", "R".repeat(rng.gen_range(3..20)), "N".repeat(rng.gen_range(1..5)))
+            } else {
+                format!("pong")
+            }
+        };
+        reply(ctx, interaction, true, response).await?;
+        Ok(())
+    })
+}
+
+fn poll<'a>(ctx: &'a Context, interaction: &'a CommandInteraction) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'a>> {
+    Box::pin(async move {
+        let options = required_option(&interaction.data.options, "options", CommandDataOptionValue::as_str)?
+            .split(';')
+            .map(str::trim)
+            .filter(|option| !option.is_empty())
+            .map(str::to_owned)
+            .collect_vec();
+        if options.len() < 2 || options.len() > poll::MAX_OPTIONS {
+            reply(ctx, interaction, true, format!("eine Umfrage braucht zwischen 2 und {} Antwortmöglichkeiten", poll::MAX_OPTIONS)).await?;
+            return Ok(())
+        }
+        let anonymous = option(&interaction.data.options, "anonymous", CommandDataOptionValue::as_bool)?.unwrap_or(false);
+        let deadline = match option(&interaction.data.options, "duration", CommandDataOptionValue::as_i64)? {
+            Some(minutes) => {
+                let Some(deadline) = TimeDelta::try_minutes(minutes).and_then(|duration| Utc::now().checked_add_signed(duration)) else {
+                    reply(ctx, interaction, true, "diese Dauer ist zu lang").await?;
+                    return Ok(())
+                };
+                Some(deadline)
+            }
+            None => None,
+        };
+        let poll = poll::Poll {
+            question: required_option(&interaction.data.options, "question", CommandDataOptionValue::as_str)?.to_owned(),
+            multi: option(&interaction.data.options, "multi", CommandDataOptionValue::as_bool)?.unwrap_or(false),
+            buttons: anonymous || option(&interaction.data.options, "buttons", CommandDataOptionValue::as_bool)?.unwrap_or(false),
+            options, anonymous, deadline,
+        };
+        let pool = peter::database(&*ctx.data.read().await)?;
+        // posting a reaction poll takes a while, so the interaction is responded to first
+        reply(ctx, interaction, true, "Umfrage wird erstellt…").await?;
+        poll::create(ctx, &pool, interaction.channel_id, interaction.user.id, poll).await?;
+        Ok(())
+    })
+}
+
+fn promote<'a>(ctx: &'a Context, interaction: &'a CommandInteraction) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'a>> {
+    Box::pin(async move {
+        let guild_id = interaction.guild_id.ok_or(Error::NotInGuild)?;
+        if !has_role(ctx, interaction, |guild| guild.admin).await? {
+            reply(ctx, interaction, true, "nur Admins können Gäste befördern").await?;
+            return Ok(())
+        }
+        let user_id = required_option(&interaction.data.options, "user", CommandDataOptionValue::as_user_id)?;
+        let pool = peter::database(&*ctx.data.read().await)?;
+        if let Err(response) = guests::promote(ctx, &pool, guild_id, user_id).await? {
+            reply(ctx, interaction, true, response).await?;
+            return Ok(())
+        }
+        reply(ctx, interaction, true, MessageBuilder::default()
+            .mention(&user_id)
+            .push(" ist jetzt ein Mensch")
+            .build()
+        ).await?;
+        Ok(())
+    })
+}
+
+fn quiz<'a>(ctx: &'a Context, interaction: &'a CommandInteraction) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'a>> {
+    Box::pin(async move {
+        let guild_id = interaction.guild_id.ok_or(Error::NotInGuild)?;
+        if !has_role(ctx, interaction, |guild| guild.quizmaster).await? {
+            reply(ctx, interaction, true, "nur der Quizmaster kann das Quiz moderieren").await?;
+            return Ok(())
+        }
+        let (subcommand, options) = subcommand(interaction)?;
+        let num_teams = quiz::num_teams(ctx.data.read().await.get::<Config>().expect("missing config"), guild_id);
+        let pool = peter::database(&*ctx.data.read().await)?;
+        // the quiz state is only locked while it's being updated, not while talking to Discord or the database
+        let response = match subcommand {
+            "start" => if ctx.data.read().await.get::<Quiz>().expect("missing quiz state").contains_key(&guild_id) {
+                "auf diesem Server läuft schon ein Quiz".to_owned()
+            } else {
+                let quiz = Quiz::start(ctx, interaction.channel_id).await?;
+                let mut data = ctx.data.write().await;
+                match data.get_mut::<Quiz>().expect("missing quiz state").entry(guild_id) {
+                    hash_map::Entry::Vacant(entry) => {
+                        entry.insert(quiz);
+                        "Quiz gestartet".to_owned()
+                    }
+                    hash_map::Entry::Occupied(_) => {
+                        // another quiz was started while the scoreboard was being posted
+                        drop(data);
+                        quiz.discard(ctx).await?;
+                        "auf diesem Server läuft schon ein Quiz".to_owned()
+                    }
+                }
+            },
+            "end" => {
+                let quiz = ctx.data.write().await.get_mut::<Quiz>().expect("missing quiz state").remove(&guild_id);
+                if let Some(quiz) = quiz {
+                    quiz.end(ctx, &pool, guild_id).await?;
+                    "Quiz beendet".to_owned()
+                } else {
+                    "auf diesem Server läuft aktuell kein Quiz".to_owned()
+                }
+            }
+            _ => {
+                let result = ctx.data.write().await.get_mut::<Quiz>().expect("missing quiz state").get_mut(&guild_id).map(|quiz| match subcommand {
+                    "round" => Ok((Some(quiz.open_round(option(options, "question", CommandDataOptionValue::as_str)?)), "Runde eröffnet".to_owned())),
+                    "close" => Ok(match quiz.close_round() {
+                        Some(update) => (Some(update), "Runde beendet".to_owned()),
+                        None => (None, "aktuell läuft keine Runde".to_owned()),
+                    }),
+                    "points" => {
+                        let team = required_option(options, "team", CommandDataOptionValue::as_i64)?;
+                        let team_idx = usize::try_from(team - 1).ok().filter(|&team_idx| team_idx < num_teams).ok_or(Error::CommandOption("team"))?;
+                        let (score, update) = quiz.award(team_idx, required_option(options, "points", CommandDataOptionValue::as_i64)?);
+                        Ok((Some(update), format!("Team {team} hat jetzt {score} {}", if score == 1 { "Punkt" } else { "Punkte" })))
+                    }
+                    _ => Err(Error::CommandOption("subcommand")),
+                }).transpose()?;
+                if let Some((update, response)) = result {
+                    if let Some(update) = update {
+                        update.send(ctx).await?;
+                    }
+                    response
+                } else {
+                    "auf diesem Server läuft aktuell kein Quiz".to_owned()
+                }
+            }
+        };
+        reply(ctx, interaction, true, response).await?;
+        Ok(())
+    })
+}
+
+fn reload_config<'a>(ctx: &'a Context, interaction: &'a CommandInteraction) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'a>> {
+    Box::pin(async move {
+        if !has_role(ctx, interaction, |guild| guild.admin).await? {
+            reply(ctx, interaction, true, "nur Admins können die Konfiguration neu laden").await?;
+            return Ok(())
+        }
+        interaction.defer_ephemeral(ctx).await?;
+        let response = match Config::reload(ctx).await {
+            Ok(warnings) => {
+                // the config determines which commands are available and their options, e.g. the number of teams
+                for guild_id in ctx.cache.guilds() {
+                    register(ctx, guild_id).await?;
+                }
+                if warnings.is_empty() {
+                    "Konfiguration neu geladen".to_owned()
+                } else {
+                    // only the first few warnings are shown to stay within Discord's message length limit
+                    format!("Konfiguration neu geladen, {} Warnungen:\n{}", warnings.len(), warnings.iter().take(10).join("\n"))
+                }
+            }
+            Err(e) => format!("die Konfiguration ist ungültig, die alte bleibt aktiv: {e}"),
+        };
+        interaction.edit_response(ctx, EditInteractionResponse::new().content(response)).await?;
+        Ok(())
+    })
+}
+
+fn reset_quiz<'a>(ctx: &'a Context, interaction: &'a CommandInteraction) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'a>> {
+    Box::pin(async move {
+        let guild_id = interaction.guild_id.ok_or(Error::NotInGuild)?;
+        if !has_role(ctx, interaction, |guild| guild.quizmaster).await? {
+            reply(ctx, interaction, true, "nur der Quizmaster kann die Teams aufräumen").await?;
+            return Ok(())
+        }
+        let pool = peter::database(&*ctx.data.read().await)?;
+        // going through all members takes a while, so the interaction is deferred
+        interaction.defer_ephemeral(ctx).await?;
+        quiz::reset_teams(ctx, &pool, guild_id).await?;
+        interaction.edit_response(ctx, EditInteractionResponse::new().content("Teams aufgeräumt")).await?;
+        Ok(())
+    })
+}
+
+fn roles<'a>(ctx: &'a Context, interaction: &'a CommandInteraction) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'a>> {
+    Box::pin(async move {
+        let guild_id = interaction.guild_id.ok_or(Error::NotInGuild)?;
+        if !has_role(ctx, interaction, |guild| guild.admin).await? {
+            reply(ctx, interaction, true, "nur Admins können die Rollenauswahl posten").await?;
+            return Ok(())
+        }
+        let messages = roles::menu(ctx, guild_id).await?;
+        if messages.is_empty() {
+            reply(ctx, interaction, true, "es gibt keine selbstzuweisbaren Rollen").await?;
+            return Ok(())
+        }
+        reply(ctx, interaction, true, "Rollenauswahl wird gepostet…").await?;
+        for message in messages {
+            interaction.channel_id.send_message(ctx, message).await?;
+        }
+        Ok(())
+    })
+}
+
+fn selfassign<'a>(ctx: &'a Context, interaction: &'a CommandInteraction) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'a>> {
+    Box::pin(async move {
+        if !has_role(ctx, interaction, |guild| guild.admin).await? {
+            reply(ctx, interaction, true, "nur Admins können selbstzuweisbare Rollen verwalten").await?;
+            return Ok(())
+        }
+        let (subcommand, options) = subcommand(interaction)?;
+        let role_id = required_option(options, "role", CommandDataOptionValue::as_role_id)?;
+        if !matches!(subcommand, "add" | "remove") { return Err(Error::CommandOption("subcommand")) }
+        let changed = Config::update(ctx, |config| if subcommand == "add" {
+            config.peter.self_assignable_roles.insert(role_id)
+        } else {
+            let mut changed = config.peter.self_assignable_roles.remove(&role_id);
+            for category in &mut config.peter.role_categories {
+                let len = category.roles.len();
+                category.roles.retain(|&role| role != role_id);
+                changed |= category.roles.len() < len;
+            }
+            changed
+        }).await?;
+        let response = match (subcommand, changed) {
+            ("add", true) => "die Rolle ist jetzt selbstzuweisbar",
+            ("add", false) => "die Rolle ist schon selbstzuweisbar",
+            (_, true) => "die Rolle ist jetzt nicht mehr selbstzuweisbar",
+            (_, false) => "die Rolle war nicht selbstzuweisbar",
+        };
+        reply(ctx, interaction, true, response).await?;
+        Ok(())
+    })
+}
+
+fn stream<'a>(ctx: &'a Context, interaction: &'a CommandInteraction) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'a>> {
+    Box::pin(async move {
+        let (subcommand, options) = subcommand(interaction)?;
+        match required_option(options, "platform", CommandDataOptionValue::as_str)? {
+            Twitch::PLATFORM => stream_subcommand::<Twitch>(ctx, interaction, subcommand, options).await,
+            YouTube::PLATFORM => stream_subcommand::<YouTube>(ctx, interaction, subcommand, options).await,
+            _ => Err(Error::CommandOption("platform")),
+        }
+    })
+}
+
+async fn stream_subcommand<P: StreamProvider>(ctx: &Context, interaction: &CommandInteraction, subcommand: &str, options: &[CommandDataOption]) -> Result<(), Error> {
+    let user = interaction.user.id;
+    match subcommand {
+        "link" => {
+            let account = required_option(options, "account", CommandDataOptionValue::as_str)?;
+            match stream::link::<P>(ctx, user, account).await {
+                Ok(Some(display_name)) => reply(ctx, interaction, true, MessageBuilder::default().push(format!("{}-Account verknüpft: ", P::NAME)).push_safe(display_name).build()).await?,
+                Ok(None) => reply(ctx, interaction, true, format!("es gibt keinen {}-Account mit diesem Namen", P::NAME)).await?,
+                // the command may still offer a platform which was removed from the config until it's registered again
+                Err(Error::PlatformNotConfigured(_)) => reply(ctx, interaction, true, format!("{} ist auf diesem Server nicht eingerichtet", P::NAME)).await?,
+                Err(e) => return Err(e),
+            }
+        }
+        "unlink" => {
+            let pool = peter::database(&*ctx.data.read().await)?;
+            if stream::unlink::<P>(&pool, user).await? {
+                reply(ctx, interaction, true, "Verknüpfung entfernt").await?;
+            } else {
+                reply(ctx, interaction, true, format!("du hast keinen {}-Account verknüpft", P::NAME)).await?;
+            }
+        }
+        "announce" => {
+            let enabled = required_option(options, "enabled", CommandDataOptionValue::as_bool)?;
+            if stream::set_announce::<P>(ctx, user, enabled).await? {
+                reply(ctx, interaction, true, if enabled { "deine Streams werden angekündigt" } else { "deine Streams werden nicht mehr angekündigt" }).await?;
+            } else {
+                reply(ctx, interaction, true, format!("du hast keinen {}-Account verknüpft, benutze zuerst /stream link", P::NAME)).await?;
+            }
+        }
+        _ => return Err(Error::CommandOption("subcommand")),
+    }
+    Ok(())
+}
+
+fn team<'a>(ctx: &'a Context, interaction: &'a CommandInteraction) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'a>> {
+    Box::pin(async move {
+        let member = member(interaction)?;
+        let mut teams = Teams::get(ctx, member.guild_id).await?.ok_or(Error::NoTeams)?;
+        let team = required_option(&interaction.data.options, "team", CommandDataOptionValue::as_i64)?;
+        // the option's range is set when registering the command, but the number of teams may have changed since
+        let Some(team_idx) = usize::try_from(team - 1).ok().filter(|&team_idx| team_idx < teams.len()) else {
+            reply(ctx, interaction, true, format!("es gibt nur die Teams 1 bis {}", teams.len())).await?;
+            return Ok(())
+        };
+        let pool = peter::database(&*ctx.data.read().await)?;
+        quiz::join_team(ctx, &pool, &mut teams, member, team_idx).await?;
+        reply(ctx, interaction, true, format!("du bist jetzt in Team {team}")).await?;
+        Ok(())
+    })
+}
+
+fn teams<'a>(ctx: &'a Context, interaction: &'a CommandInteraction) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'a>> {
+    Box::pin(async move {
+        let guild_id = interaction.guild_id.ok_or(Error::NotInGuild)?;
+        if !has_role(ctx, interaction, |guild| guild.quizmaster).await? {
+            reply(ctx, interaction, true, "nur der Quizmaster kann Teams einteilen").await?;
+            return Ok(())
+        }
+        let (subcommand, options) = subcommand(interaction)?;
+        if subcommand != "shuffle" { return Err(Error::CommandOption("subcommand")) }
+        let count = required_option(options, "count", CommandDataOptionValue::as_i64)?;
+        let mut teams = Teams::get(ctx, guild_id).await?.ok_or(Error::NoTeams)?;
+        let count = usize::try_from(count).ok().filter(|&count| count >= 1 && count <= teams.len()).ok_or(Error::CommandOption("count"))?;
+        let (pool, quizmaster, users) = {
+            let data = ctx.data.read().await;
+            let VoiceStates(ref chan_map) = data.get::<VoiceStates>().expect("missing voice states map");
+            (
+                peter::database(&data)?,
+                data.get::<Config>().expect("missing config").guilds.get(&guild_id).and_then(|guild_config| guild_config.quizmaster),
+                chan_map.values().find(|(_, users)| users.contains(&interaction.user)).map(|(_, users)| users.clone()),
+            )
+        };
+        let Some(users) = users else {
+            reply(ctx, interaction, true, "du musst in einem Voice-Channel sein, damit die Leute dort in Teams eingeteilt werden können").await?;
+            return Ok(())
+        };
+        // assigning roles and nicknames takes a while, so the interaction is deferred
+        interaction.defer(ctx).await?;
+        let mut players = Vec::default();
+        for user in users {
+            if user.bot { continue }
+            let member = guild_id.member(ctx, user.id).await?;
+            if quizmaster.map_or(true, |quizmaster| !member.roles.contains(&quizmaster)) {
+                players.push(member);
+            }
+        }
+        players.shuffle(&mut thread_rng());
+        let mut assignments = vec![Vec::default(); count];
+        for (idx, member) in players.iter().enumerate() {
+            let team_idx = idx % count;
+            quiz::join_team(ctx, &pool, &mut teams, member, team_idx).await?;
+            assignments[team_idx].push(member.user.id);
+        }
+        let mut builder = MessageBuilder::default();
+        builder.push("Die Teams:");
+        for (team_idx, members) in assignments.into_iter().enumerate() {
+            builder.push_line("").push_bold(format!("Team {}", team_idx + 1)).push(": ");
+            if members.is_empty() {
+                builder.push("(niemand)");
+            }
+            for (idx, member) in members.into_iter().enumerate() {
+                if idx > 0 { builder.push(", "); }
+                builder.mention(&member);
+            }
+        }
+        interaction.edit_response(ctx, EditInteractionResponse::new().content(builder.build())).await?;
+        Ok(())
+    })
+}
@@ -8,36 +8,27 @@ use {
             BTreeSet,
             HashMap,
         },
+        convert::Infallible as Never,
+        env,
         future::Future,
-        iter,
-        pin::{
-            Pin,
-            pin,
-        },
+        pin::Pin,
+        process,
+        sync::Arc,
         time::{
             Duration,
             Instant,
         },
     },
-    futures::stream::TryStreamExt as _,
     itertools::Itertools as _,
-    rand::prelude::*,
     serde_json::json,
     serenity::{
-        all::{
-            CreateCommand,
-            CreateCommandOption,
-            CreateInteractionResponse,
-            CreateInteractionResponseMessage,
-            EditMember,
-        },
         futures::TryFutureExt as _,
         model::prelude::*,
         prelude::*,
         utils::MessageBuilder,
     },
     serenity_utils::{
-        builder::ErrorNotifier,
+        RwFuture,
         handler::{
             HandlerMethods as _,
             voice_state::VoiceStates,
@@ -55,30 +46,33 @@ use {
     peter::{
         Database,
         Error,
-        FENHL,
-        GEFOLGE,
-        QUIZMASTER,
-        config::Config,
+        config::{
+            self,
+            Config,
+        },
+        guests,
+        poll,
+        quiz,
+        stream::{
+            self,
+            StreamProvider,
+        },
         twitch,
+        user_list,
+        welcome,
         werewolf,
+        youtube,
     },
 };
 
-const TEAMS: [RoleId; 6] = [
-    RoleId::new(828431321586991104),
-    RoleId::new(828431500747735100),
-    RoleId::new(828431624759935016),
-    RoleId::new(828431736194072606),
-    RoleId::new(828431741332750407),
-    RoleId::new(828431913738960956),
-];
+mod commands;
 
 enum VoiceStateExporter {}
 
 impl serenity_utils::handler::voice_state::ExporterMethods for VoiceStateExporter {
-    fn dump_info<'a>(_: &'a Context, guild_id: GuildId, VoiceStates(voice_states): &'a VoiceStates) -> Pin<Box<dyn Future<Output = Result<(), Box<dyn std::error::Error + Send + Sync>>> + Send + 'a>> {
+    fn dump_info<'a>(ctx: &'a Context, guild_id: GuildId, VoiceStates(voice_states): &'a VoiceStates) -> Pin<Box<dyn Future<Output = Result<(), Box<dyn std::error::Error + Send + Sync>>> + Send + 'a>> {
         Box::pin(async move {
-            if guild_id != GEFOLGE { return Ok(()) }
+            if guild_id != ctx.data.read().await.get::<Config>().expect("missing config").main_guild { return Ok(()) }
             let buf = serde_json::to_vec_pretty(&json!({
                 "channels": voice_states.into_iter()
                     .map(|(channel_id, (channel_name, members))| json!({
@@ -108,9 +102,9 @@ impl serenity_utils::handler::voice_state::ExporterMethods for VoiceStateExporte
 
     fn notify_start<'a>(ctx: &'a Context, user_id: UserId, guild_id: GuildId, channel_id: ChannelId) -> Pin<Box<dyn Future<Output = Result<(), Box<dyn std::error::Error + Send + Sync>>> + Send + 'a>> {
         Box::pin(async move {
-            if guild_id != GEFOLGE { return Ok(()) }
             let data = ctx.data.read().await;
             let config = data.get::<Config>().expect("missing config");
+            if guild_id != config.main_guild { return Ok(()) }
             let mut msg_builder = MessageBuilder::default();
             msg_builder.push("Discord Party? ");
             msg_builder.mention(&user_id);
@@ -122,38 +116,129 @@ impl serenity_utils::handler::voice_state::ExporterMethods for VoiceStateExporte
     }
 }
 
-#[derive(Clone, Copy)]
-pub(crate) struct CommandIds {
-    day: CommandId,
-    iam: Option<CommandId>,
-    iamn: Option<CommandId>,
-    r#in: CommandId,
-    night: CommandId,
-    out: CommandId,
-    ping: Option<CommandId>,
-    reset_quiz: Option<CommandId>,
-    team: Option<CommandId>,
+/// Implements `peter check-config`, which checks the config file for problems without connecting to Discord and exits.
+async fn check_config() -> Result<Never, Error> {
+    let path = config::path();
+    // warnings don't prevent Peter from starting, but they're usually mistakes, so they fail the check
+    let (_, mut problems, warnings) = Config::check(&fs::read_to_string(&path).await?);
+    problems.extend(warnings);
+    if problems.is_empty() {
+        println!("{}: no problems found", path.display());
+        process::exit(0)
+    } else {
+        for problem in &problems {
+            eprintln!("{}: {problem}", path.display());
+        }
+        process::exit(1)
+    }
 }
 
-impl TypeMapKey for CommandIds {
-    type Value = HashMap<GuildId, CommandIds>;
+/// Returns a database connection pool which only connects once it's first used.
+///
+/// Peter can run without a database if it's disabled in the config, in which case features which store data there, like polls and quizzes, are unavailable.
+fn connect_database() -> PgPool {
+    PgPool::connect_lazy_with(PgConnectOptions::default().database("gefolge").application_name("peter"))
+}
+
+/// Implements `peter migrate`, which sets up or updates the database schema and exits.
+async fn migrate() -> Result<Never, Error> {
+    peter::migrate(&connect_database()).await?;
+    println!("database is up to date");
+    process::exit(0)
+}
+
+/// Reports a command-line usage error and exits.
+fn usage(msg: &str) -> ! {
+    eprintln!("{msg}");
+    eprintln!("usage: peter [--config <path>] [check-config | migrate]");
+    process::exit(2)
+}
+
+/// Reports a crashed background task to all configured error notifiers, falling back to `serenity_utils`, which only notifies the first one, if that fails.
+async fn notify_task_crash<N, F>(ctx_fut: &RwFuture<Context>, notify_thread_crash: &N, source: &str, e: Error, wait_time: Duration)
+where N: Fn(String, Box<dyn std::error::Error + Send + Sync>, Option<Duration>) -> F, F: Future<Output = ()> {
+    let notified = {
+        let ctx = ctx_fut.read().await;
+        peter::notify_error(&ctx, &format!("{source} (retrying in {wait_time:?})"), &e).await
+    };
+    if notified.is_err() {
+        notify_thread_crash(source.to_owned(), Box::new(e), Some(wait_time)).await;
+    }
+}
+
+/// Runs a background task, restarting it with exponential backoff if it fails.
+async fn restart_with_backoff<T, Fut, N, F>(ctx_fut: RwFuture<Context>, notify_thread_crash: N, source: &str, task: T) -> Never
+where T: Fn(RwFuture<Context>) -> Fut, Fut: Future<Output = Result<Never, Error>>, N: Fn(String, Box<dyn std::error::Error + Send + Sync>, Option<Duration>) -> F, F: Future<Output = ()> {
+    let mut last_crash = Instant::now();
+    let mut wait_time = Duration::from_secs(1);
+    loop {
+        let e = match task(ctx_fut.clone()).await {
+            Ok(never) => match never {},
+            Err(e) => e,
+        };
+        if last_crash.elapsed() >= Duration::from_secs(60 * 60 * 24) {
+            wait_time = Duration::from_secs(1); // reset wait time after no crash for a day
+        } else {
+            wait_time *= 2; // exponential backoff
+        }
+        eprintln!("{}", e);
+        if wait_time >= Duration::from_secs(if e.is_network_error() { 60 } else { 2 }) { // only notify on multiple consecutive errors
+            notify_task_crash(&ctx_fut, &notify_thread_crash, source, e, wait_time).await;
+        }
+        sleep(wait_time).await; // wait before attempting to reconnect
+        last_crash = Instant::now();
+    }
 }
 
 #[serenity_utils::main(ipc = "peter::ipc")]
 
 async fn main() -> Result<serenity_utils::Builder, Error> {
+    let mut args = env::args().skip(1);
+    let mut subcommand = None;
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            let Some(path) = args.next() else { usage("--config requires a path") };
+            config::set_path(path.into());
+        } else if let Some(path) = arg.strip_prefix("--config=") {
+            config::set_path(path.into());
+        } else if arg.starts_with('-') {
+            usage(&format!("unknown option: {arg}"))
+        } else if subcommand.is_none() {
+            subcommand = Some(arg);
+        } else {
+            usage(&format!("unexpected argument: {arg}"))
+        }
+    }
+    match subcommand.as_deref() {
+        Some("check-config") => match check_config().await? {},
+        Some("migrate") => match migrate().await? {},
+        Some(subcommand) => usage(&format!("unknown subcommand: {subcommand}")),
+        None => {}
+    }
     let config = Config::new().await?;
+    let pool = if config.database {
+        let pool = connect_database();
+        peter::migrate(&pool).await?;
+        Some(pool)
+    } else {
+        None
+    };
     Ok(serenity_utils::builder(config.peter.bot_token.clone()).await?
-        .error_notifier(ErrorNotifier::User(FENHL))
-        .event_handler(serenity_utils::handler::user_list_exporter::<peter::user_list::Exporter>())
+        .error_notifier(serenity_utils::builder::ErrorNotifier::from(config.error_notifiers[0])) // at least one error notifier is required by Config::check
+        .event_handler(serenity_utils::handler::user_list_exporter::<user_list::Exporter>())
         .event_handler(serenity_utils::handler::voice_state_exporter::<VoiceStateExporter>())
         .plain_message(|ctx, msg| Box::pin(async move {
             (msg.guild_id.is_none() || ctx.data.read().await.get::<Config>().expect("missing config").werewolf.iter().any(|(_, conf)| conf.text_channel == msg.channel_id)) && {
                 if let Some(action) = werewolf::parse_action(ctx, msg.author.id, &msg.content).await {
                     match async move { action }.and_then(|action| werewolf::handle_action(ctx, msg, action)).await {
                         Ok(()) => {} // reaction is posted in handle_action
-                        Err(Error::GameAction(err_msg)) => { msg.reply(ctx, &err_msg).await.expect("failed to reply to game action"); }
-                        Err(e) => { panic!("failed to handle game action: {}", e); }
+                        Err(Error::GameAction(err_msg)) => if let Err(e) = msg.reply(ctx, &err_msg).await {
+                            let _ = peter::notify_error(ctx, "game action reply", &e.into()).await;
+                        },
+                        Err(e) => {
+                            let _ = msg.reply(ctx, "Fehler beim Verarbeiten dieser Aktion, der Fehler wurde gemeldet").await;
+                            let _ = peter::notify_error(ctx, "game action", &e).await;
+                        }
                     }
                     true
                 } else {
@@ -163,359 +248,67 @@ async fn main() -> Result<serenity_utils::Builder, Error> {
         }))
         .unrecognized_message("ich habe diese Nachricht nicht verstanden")
         .on_guild_create(false, |ctx, guild, _| Box::pin(async move {
-            let mut commands = Vec::default();
-            let day = {
-                let idx = commands.len();
-                commands.push(CreateCommand::new("day")
-                    .kind(CommandType::ChatInput)
-                    .dm_permission(false)
-                );
-                idx
-            };
-            let iam = (guild.id == GEFOLGE).then(|| {
-                let idx = commands.len();
-                commands.push(CreateCommand::new("iam")
-                    .kind(CommandType::ChatInput)
-                    .dm_permission(false)
-                    .description("Dir eine selbstzuweisbare Rolle zuweisen")
-                    .add_option(CreateCommandOption::new(
-                        CommandOptionType::Role,
-                        "role",
-                        "die Rolle, die du haben möchtest",
-                    ).required(true))
-                );
-                idx
-            });
-            let iamn = (guild.id == GEFOLGE).then(|| {
-                let idx = commands.len();
-                commands.push(CreateCommand::new("iamn")
-                    .kind(CommandType::ChatInput)
-                    .dm_permission(false)
-                    .description("Eine selbstzuweisbare Rolle von dir entfernen")
-                    .add_option(CreateCommandOption::new(
-                        CommandOptionType::Role,
-                        "role",
-                        "die Rolle, die du loswerden möchtest",
-                    ).required(true))
-                );
-                idx
-            });
-            let r#in = {
-                let idx = commands.len();
-                commands.push(CreateCommand::new("in")
-                    .kind(CommandType::ChatInput)
-                    .dm_permission(false)
-                );
-                idx
-            };
-            let night = {
-                let idx = commands.len();
-                commands.push(CreateCommand::new("night")
-                    .kind(CommandType::ChatInput)
-                    .dm_permission(false)
-                );
-                idx
-            };
-            let out = {
-                let idx = commands.len();
-                commands.push(CreateCommand::new("out")
-                    .kind(CommandType::ChatInput)
-                    .dm_permission(false)
-                );
-                idx
-            };
-            let ping = (guild.id == GEFOLGE).then(|| {
-                let idx = commands.len();
-                commands.push(CreateCommand::new("ping")
-                    .kind(CommandType::ChatInput)
-                    .dm_permission(false)
-                    .description("Testen, ob Peter online ist")
-                );
-                idx
-            });
-            let reset_quiz = (guild.id == GEFOLGE).then(|| {
-                let idx = commands.len();
-                commands.push(CreateCommand::new("reset-quiz")
-                    .kind(CommandType::ChatInput)
-                    .dm_permission(false)
-                    .description("Die Rollen und Nicknames für Quizmaster und Teams aufräumen")
-                );
-                idx
-            });
-            let team = (guild.id == GEFOLGE).then(|| {
-                let idx = commands.len();
-                commands.push(CreateCommand::new("team")
-                    .kind(CommandType::ChatInput)
-                    .dm_permission(false)
-                    .description("In ein Team wechseln, z.B. für ein Quiz")
-                    .add_option(CreateCommandOption::new(
-                        CommandOptionType::Integer,
-                        "team",
-                        "Die Teamnummer",
-                    )
-                        .required(true)
-                        .min_int_value(1)
-                        .max_int_value(6)
-                    )
-                );
-                idx
-            });
-            let commands = guild.set_commands(ctx, commands).await?;
-            ctx.data.write().await.entry::<CommandIds>().or_default().insert(guild.id, CommandIds {
-                day: commands[day].id,
-                iam: iam.map(|idx| commands[idx].id),
-                iamn: iamn.map(|idx| commands[idx].id),
-                r#in: commands[r#in].id,
-                night: commands[night].id,
-                out: commands[out].id,
-                ping: ping.map(|idx| commands[idx].id),
-                reset_quiz: reset_quiz.map(|idx| commands[idx].id),
-                team: team.map(|idx| commands[idx].id),
-            });
+            let result: Result<(), Error> = async {
+                commands::register(ctx, guild.id).await?;
+                let problems = ctx.data.read().await.get::<Config>().expect("missing config").check_guild(ctx, guild.id).await?;
+                if !problems.is_empty() {
+                    return Err(Error::InvalidConfig(problems))
+                }
+                Ok(())
+            }.await;
+            // reported here rather than by serenity_utils, which only notifies the first error notifier
+            if let Err(e) = result {
+                peter::notify_error(ctx, "guild create", &e).await?;
+            }
+            Ok(())
+        }))
+        .on_guild_member_addition(|ctx, member| Box::pin(async move {
+            if let Err(e) = welcome::greet(ctx, member).await {
+                peter::notify_error(ctx, "welcome", &e).await?;
+            }
             Ok(())
         }))
         .on_interaction_create(|ctx, interaction| Box::pin(async move {
-            match interaction {
-                Interaction::Command(interaction) => {
-                    let guild_id = interaction.guild_id.expect("Discord slash command called outside of a guild");
-                    if let Some(&command_ids) = ctx.data.read().await.get::<CommandIds>().and_then(|command_ids| command_ids.get(&guild_id)) {
-                        if interaction.data.id == command_ids.day {
-                            match werewolf::channel_check(ctx, &interaction).await {
-                                Ok(guild) => {
-                                    let data = ctx.data.read().await;
-                                    let conf = *data.get::<Config>().expect("missing config").werewolf.get(&guild).expect("unconfigured guild but check passed");
-                                    if let Some(voice_channel) = conf.voice_channel {
-                                        let voice_states = data.get::<VoiceStates>().expect("missing voice states map");
-                                        let VoiceStates(ref chan_map) = voice_states;
-                                        if let Some((_, users)) = chan_map.get(&voice_channel) {
-                                            for user in users {
-                                                guild.edit_member(ctx, user, EditMember::default().mute(false)).await?;
-                                            }
-                                        }
-                                    }
-                                }
-                                Err(response) => interaction.create_response(ctx, CreateInteractionResponse::Message(CreateInteractionResponseMessage::new()
-                                    .ephemeral(true)
-                                    .content(response)
-                                )).await?,
-                            }
-                        } else if Some(interaction.data.id) == command_ids.iam {
-                            let member = interaction.member.clone().expect("/iam called outside of a guild");
-                            let role_id = match interaction.data.options[0].value {
-                                CommandDataOptionValue::Role(role) => role,
-                                _ => panic!("unexpected slash command option type"),
-                            };
-                            let response = if !ctx.data.read().await.get::<Config>().expect("missing self-assignable roles list").peter.self_assignable_roles.contains(&role_id) {
-                                "diese Rolle ist nicht selbstzuweisbar"
-                            } else if member.roles.contains(&role_id) {
-                                "du hast diese Rolle schon"
-                            } else {
-                                member.add_role(&ctx, role_id).await?;
-                                "✅"
-                            };
-                            interaction.create_response(ctx, CreateInteractionResponse::Message(CreateInteractionResponseMessage::new()
-                                .ephemeral(true)
-                                .content(response)
-                            )).await?;
-                        } else if Some(interaction.data.id) == command_ids.iamn {
-                            let member = interaction.member.clone().expect("/iamn called outside of a guild");
-                            let role_id = match interaction.data.options[0].value {
-                                CommandDataOptionValue::Role(role) => role,
-                                _ => panic!("unexpected slash command option type"),
-                            };
-                            let response = if !ctx.data.read().await.get::<Config>().expect("missing self-assignable roles list").peter.self_assignable_roles.contains(&role_id) {
-                                "diese Rolle ist nicht selbstzuweisbar"
-                            } else if !member.roles.contains(&role_id) {
-                                "du hast diese Rolle sowieso nicht"
-                            } else {
-                                member.remove_role(&ctx, role_id).await?;
-                                "✅"
-                            };
-                            interaction.create_response(ctx, CreateInteractionResponse::Message(CreateInteractionResponseMessage::new()
-                                .ephemeral(true)
-                                .content(response)
-                            )).await?;
-                        } else if interaction.data.id == command_ids.r#in {
-                            match werewolf::channel_check(ctx, &interaction).await {
-                                Ok(guild) => {
-                                    {
-                                        let mut data = ctx.data.write().await;
-                                        let conf = *data.get::<Config>().expect("missing config").werewolf.get(&guild).expect("unconfigured guild but check passed");
-                                        let state = data.get_mut::<werewolf::GameState>().expect("missing Werewolf game state");
-                                        if state.iter().any(|(&iter_guild, iter_state)| iter_guild != guild && iter_state.state.secret_ids().map_or(false, |secret_ids| secret_ids.contains(&interaction.user.id))) {
-                                            interaction.create_response(ctx, CreateInteractionResponse::Message(CreateInteractionResponseMessage::new()
-                                                .ephemeral(true)
-                                                .content("du bist schon in einem Spiel auf einem anderen Server")
-                                            )).await?;
-                                            return Ok(())
-                                        }
-                                        let state = state.entry(guild).or_insert_with(|| werewolf::GameState::new(guild, conf));
-                                        if let werewolf::State::Complete(_) = state.state {
-                                            state.state = werewolf::State::default();
-                                        }
-                                        if let werewolf::State::Signups(ref mut signups) = state.state {
-                                            // sign up for game
-                                            if !signups.sign_up(interaction.user.id) {
-                                                interaction.create_response(ctx, CreateInteractionResponse::Message(CreateInteractionResponseMessage::new()
-                                                    .ephemeral(true)
-                                                    .content("du bist schon angemeldet")
-                                                )).await?;
-                                                return Ok(())
-                                            }
-                                            // add DISCUSSION_ROLE
-                                            let roles = iter::once(conf.role).chain(interaction.member.as_ref().unwrap().roles.iter().copied());
-                                            guild.edit_member(&ctx, interaction.user.id, EditMember::default().roles(roles)).await?;
-                                            interaction.create_response(ctx, CreateInteractionResponse::Message(CreateInteractionResponseMessage::new()
-                                                .ephemeral(false)
-                                                .content("✅")
-                                            )).await?;
-                                        } else {
-                                            interaction.create_response(ctx, CreateInteractionResponse::Message(CreateInteractionResponseMessage::new()
-                                                .ephemeral(true)
-                                                .content("bitte warte, bis das aktuelle Spiel vorbei ist")
-                                            )).await?;
-                                            return Ok(())
-                                        }
-                                    }
-                                    werewolf::continue_game(&ctx, guild).await?;
-                                }
-                                Err(response) => interaction.create_response(ctx, CreateInteractionResponse::Message(CreateInteractionResponseMessage::new()
-                                    .ephemeral(true)
-                                    .content(response)
-                                )).await?,
-                            }
-                        } else if interaction.data.id == command_ids.night {
-                            match werewolf::channel_check(ctx, &interaction).await {
-                                Ok(guild) => {
-                                    let data = ctx.data.read().await;
-                                    let conf = *data.get::<Config>().expect("missing config").werewolf.get(&guild).expect("unconfigured guild but check passed");
-                                    if let Some(voice_channel) = conf.voice_channel {
-                                        let voice_states = data.get::<VoiceStates>().expect("missing voice states map");
-                                        let VoiceStates(ref chan_map) = voice_states;
-                                        if let Some((_, users)) = chan_map.get(&voice_channel) {
-                                            for user in users {
-                                                if *user != interaction.user {
-                                                    guild.edit_member(ctx, user, EditMember::default().mute(true)).await?;
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
-                                Err(response) => interaction.create_response(ctx, CreateInteractionResponse::Message(CreateInteractionResponseMessage::new()
-                                    .ephemeral(true)
-                                    .content(response)
-                                )).await?,
-                            }
-                        } else if interaction.data.id == command_ids.out {
-                            match werewolf::channel_check(ctx, &interaction).await {
-                                Ok(guild) => {
-                                    {
-                                        let mut data = ctx.data.write().await;
-                                        let conf = *data.get::<Config>().expect("missing config").werewolf.get(&guild).expect("unconfigured guild but check passed");
-                                        let state = data.get_mut::<werewolf::GameState>().expect("missing Werewolf game state").entry(guild).or_insert_with(|| werewolf::GameState::new(guild, conf));
-                                        if let werewolf::State::Complete(_) = state.state {
-                                            state.state = werewolf::State::default();
-                                        }
-                                        if let werewolf::State::Signups(ref mut signups) = state.state {
-                                            if !signups.remove_player(&interaction.user.id) {
-                                                interaction.create_response(ctx, CreateInteractionResponse::Message(CreateInteractionResponseMessage::new()
-                                                    .ephemeral(true)
-                                                    .content("du warst nicht angemeldet")
-                                                )).await?;
-                                                return Ok(())
-                                            }
-                                            // remove DISCUSSION_ROLE
-                                            let roles = interaction.member.as_ref().unwrap().roles.iter().copied().filter(|&role| role != conf.role);
-                                            guild.edit_member(&ctx, interaction.user.id, EditMember::default().roles(roles)).await?;
-                                            interaction.create_response(ctx, CreateInteractionResponse::Message(CreateInteractionResponseMessage::new()
-                                                .ephemeral(false)
-                                                .content("✅")
-                                            )).await?;
-                                        } else {
-                                            interaction.create_response(ctx, CreateInteractionResponse::Message(CreateInteractionResponseMessage::new()
-                                                .ephemeral(true)
-                                                .content("bitte warte, bis das aktuelle Spiel vorbei ist") //TODO implement forfeiting
-                                            )).await?;
-                                            return Ok(())
-                                        }
-                                    }
-                                    werewolf::continue_game(&ctx, guild).await?;
-                                }
-                                Err(response) => interaction.create_response(ctx, CreateInteractionResponse::Message(CreateInteractionResponseMessage::new()
-                                    .ephemeral(true)
-                                    .content(response)
-                                )).await?,
-                            }
-                        } else if Some(interaction.data.id) == command_ids.ping {
-                            interaction.create_response(ctx, CreateInteractionResponse::Message(CreateInteractionResponseMessage::new()
-                                .ephemeral(true)
-                                .content({
-                                    let mut rng = thread_rng();
-                                    if rng.gen_bool(0.01) {
-                                        format!("BWO{}{}G", "R".repeat(rng.gen_range(3..20)), "N".repeat(rng.gen_range(1..5)))
-                                    } else {
-                                        format!("pong")
-                                    }
-                                })
-                            )).await?;
-                        } else if Some(interaction.data.id) == command_ids.reset_quiz {
-                            let mut members = pin!(guild_id.members_iter(ctx));
-                            while let Some(member) = members.try_next().await? {
-                                member.remove_roles(&ctx, &iter::once(QUIZMASTER).chain(TEAMS).collect_vec()).await?;
-                                //TODO adjust nickname
-                            }
-                            interaction.create_response(ctx, CreateInteractionResponse::Message(CreateInteractionResponseMessage::new()
-                                .ephemeral(true)
-                                .content("Teams aufgeräumt")
-                            )).await?;
-                        } else if Some(interaction.data.id) == command_ids.team {
-                            let member = interaction.member.clone().expect("/team called outside of a guild");
-                            let team = match interaction.data.options[0].value {
-                                CommandDataOptionValue::Integer(team) => team,
-                                _ => panic!("unexpected slash command option type"),
-                            };
-                            let team_idx = (team - 1) as usize;
-                            member.remove_roles(&ctx, &TEAMS.iter().enumerate().filter_map(|(idx, &role_id)| (idx != team_idx).then(|| role_id)).collect_vec()).await?;
-                            member.add_role(ctx, TEAMS[team_idx]).await?;
-                            //TODO adjust nickname
-                            interaction.create_response(ctx, CreateInteractionResponse::Message(CreateInteractionResponseMessage::new()
-                                .ephemeral(true)
-                                .content(format!("du bist jetzt in Team {team}"))
-                            )).await?;
-                        } else {
-                            panic!("unexpected slash command")
-                        }
-                    }
+            let result = match interaction {
+                Interaction::Command(interaction) => commands::handle(ctx, interaction).await,
+                Interaction::Autocomplete(interaction) => commands::handle_autocomplete(ctx, interaction).await,
+                Interaction::Component(interaction) => commands::handle_component(ctx, interaction).await,
+                _ => Ok(()),
+            };
+            if let Err(e) = result {
+                // the original error is more useful than one from telling the user about it
+                if let Err(e) = commands::reply_error(ctx, interaction).await {
+                    eprintln!("failed to report interaction error to user: {e}");
                 }
-                Interaction::Component(_) => panic!("received message component interaction even though no message components are registered"),
-                _ => {}
+                peter::notify_error(ctx, "interaction", &e).await?;
             }
             Ok(())
         }))
         .data::<Config>(config)
-        .data::<Database>(PgPool::connect_with(PgConnectOptions::default().database("gefolge").application_name("peter")).await?)
+        .data::<Database>(pool)
+        .data::<quiz::Quiz>(HashMap::default())
         .data::<werewolf::GameState>(HashMap::default())
+        .data::<twitch::eventsub::Wakeup>(Arc::default())
+        .data::<twitch::eventsub::Revoked>(Arc::default())
         .task(|ctx_fut, notify_thread_crash| async move {
             // check Twitch stream status
-            let mut last_crash = Instant::now();
-            let mut wait_time = Duration::from_secs(1);
-            loop {
-                let e = match twitch::alerts(ctx_fut.clone()).await {
-                    Ok(never) => match never {},
-                    Err(e) => e,
-                };
-                if last_crash.elapsed() >= Duration::from_secs(60 * 60 * 24) {
-                    wait_time = Duration::from_secs(1); // reset wait time after no crash for a day
-                } else {
-                    wait_time *= 2; // exponential backoff
-                }
-                eprintln!("{}", e);
-                if wait_time >= Duration::from_secs(if e.is_network_error() { 60 } else { 2 }) { // only notify on multiple consecutive errors
-                    notify_thread_crash(format!("Twitch"), Box::new(e), Some(wait_time)).await;
-                }
-                sleep(wait_time).await; // wait before attempting to reconnect
-                last_crash = Instant::now();
-            }
+            match restart_with_backoff(ctx_fut, notify_thread_crash, twitch::Twitch::NAME, stream::alerts::<twitch::Twitch>).await {}
+        })
+        .task(|ctx_fut, notify_thread_crash| async move {
+            // check YouTube stream status, if configured
+            match restart_with_backoff(ctx_fut, notify_thread_crash, youtube::YouTube::NAME, stream::alerts::<youtube::YouTube>).await {}
+        })
+        .task(|ctx_fut, notify_thread_crash| async move {
+            // receive Twitch EventSub notifications, if configured
+            match restart_with_backoff(ctx_fut, notify_thread_crash, "Twitch EventSub", twitch::eventsub::serve).await {}
+        })
+        .task(|ctx_fut, notify_thread_crash| async move {
+            // close polls once their deadline has passed
+            match restart_with_backoff(ctx_fut, notify_thread_crash, "polls", poll::close_expired).await {}
+        })
+        .task(|ctx_fut, notify_thread_crash| async move {
+            // remove the guest role once guest access expires
+            match restart_with_backoff(ctx_fut, notify_thread_crash, "guests", guests::expire).await {}
         })
     )
 }
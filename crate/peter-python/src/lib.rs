@@ -51,6 +51,11 @@ fn user_to_id(user: Bound<'_, PyAny>) -> PyResult<UserId> {
         .map_err(|e| CommandError::new_err(e.to_string()))
 }
 
+#[pyfunction] fn reload_config() -> PyResult<()> {
+    peter_ipc::reload_config()
+        .map_err(|e| CommandError::new_err(e.to_string()))
+}
+
 #[pyfunction] fn set_display_name(user_id: Bound<'_, PyAny>, new_display_name: String) -> PyResult<()> {
     peter_ipc::set_display_name(user_to_id(user_id)?, new_display_name)
         .map_err(|e| CommandError::new_err(e.to_string()))
@@ -63,6 +68,7 @@ fn user_to_id(user: Bound<'_, PyAny>) -> PyResult<UserId> {
     m.add_wrapped(wrap_pyfunction!(channel_msg))?;
     m.add_wrapped(wrap_pyfunction!(msg))?;
     m.add_wrapped(wrap_pyfunction!(quit))?;
+    m.add_wrapped(wrap_pyfunction!(reload_config))?;
     m.add_wrapped(wrap_pyfunction!(set_display_name))?;
     Ok(())
 }
@@ -0,0 +1,70 @@
+-- Tables which already exist on the production database are created with IF NOT EXISTS so this migration can be applied there.
+
+CREATE TABLE IF NOT EXISTS users (
+    snowflake INT8 PRIMARY KEY,
+    discriminator INT2,
+    joined TIMESTAMPTZ,
+    nick TEXT,
+    roles JSONB NOT NULL,
+    username TEXT NOT NULL,
+    -- user data managed by gefolge.org
+    data JSONB
+);
+
+ALTER TABLE users ADD COLUMN IF NOT EXISTS member BOOLEAN NOT NULL DEFAULT TRUE;
+ALTER TABLE users ADD COLUMN IF NOT EXISTS left_at TIMESTAMPTZ;
+
+CREATE TABLE IF NOT EXISTS user_history (
+    id INT8 GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    snowflake INT8 NOT NULL,
+    at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    nick TEXT,
+    roles JSONB NOT NULL,
+    username TEXT NOT NULL,
+    member BOOLEAN NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS user_history_snowflake ON user_history (snowflake, at);
+
+CREATE TABLE IF NOT EXISTS polls (
+    message INT8 PRIMARY KEY,
+    channel INT8 NOT NULL,
+    author INT8 NOT NULL,
+    question TEXT NOT NULL,
+    options TEXT[] NOT NULL,
+    multi BOOLEAN NOT NULL,
+    anonymous BOOLEAN NOT NULL,
+    buttons BOOLEAN NOT NULL,
+    deadline TIMESTAMPTZ,
+    closed BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+CREATE TABLE IF NOT EXISTS poll_votes (
+    poll INT8 NOT NULL REFERENCES polls (message) ON DELETE CASCADE,
+    voter INT8 NOT NULL,
+    option INT2 NOT NULL,
+    PRIMARY KEY (poll, voter, option)
+);
+
+CREATE TABLE IF NOT EXISTS quizzes (
+    id INT8 GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    guild INT8 NOT NULL,
+    channel INT8 NOT NULL,
+    rounds INT4 NOT NULL,
+    ended_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE IF NOT EXISTS quiz_scores (
+    quiz INT8 NOT NULL REFERENCES quizzes (id) ON DELETE CASCADE,
+    team INT2 NOT NULL,
+    points INT8 NOT NULL,
+    PRIMARY KEY (quiz, team)
+);
+
+CREATE TABLE IF NOT EXISTS team_nicknames (
+    guild INT8 NOT NULL,
+    member INT8 NOT NULL,
+    -- the nickname before joining a team, NULL if the member had none
+    nick TEXT,
+    PRIMARY KEY (guild, member)
+);
@@ -0,0 +1,8 @@
+CREATE TABLE guests (
+    guild INT8 NOT NULL,
+    member INT8 NOT NULL,
+    granted_by INT8 NOT NULL,
+    granted_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (guild, member)
+);
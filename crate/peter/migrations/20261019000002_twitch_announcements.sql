@@ -0,0 +1,12 @@
+CREATE TABLE twitch_announcements (
+    stream_id TEXT PRIMARY KEY,
+    discord_user INT8 NOT NULL,
+    channel INT8 NOT NULL,
+    message INT8 NOT NULL,
+    title TEXT NOT NULL,
+    url TEXT NOT NULL,
+    game TEXT NOT NULL,
+    started_at TIMESTAMPTZ NOT NULL,
+    ended_at TIMESTAMPTZ,
+    delete_at TIMESTAMPTZ
+);
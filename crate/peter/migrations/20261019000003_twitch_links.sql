@@ -0,0 +1,5 @@
+CREATE TABLE twitch_links (
+    discord_user INT8 PRIMARY KEY,
+    twitch_user TEXT NOT NULL,
+    announce BOOLEAN NOT NULL DEFAULT TRUE
+);
@@ -0,0 +1,12 @@
+ALTER TABLE twitch_announcements RENAME TO stream_announcements;
+ALTER TABLE stream_announcements ADD COLUMN platform TEXT NOT NULL DEFAULT 'twitch';
+ALTER TABLE stream_announcements ALTER COLUMN platform DROP DEFAULT;
+ALTER TABLE stream_announcements DROP CONSTRAINT twitch_announcements_pkey;
+ALTER TABLE stream_announcements ADD PRIMARY KEY (platform, stream_id);
+
+ALTER TABLE twitch_links RENAME TO stream_links;
+ALTER TABLE stream_links RENAME COLUMN twitch_user TO account;
+ALTER TABLE stream_links ADD COLUMN platform TEXT NOT NULL DEFAULT 'twitch';
+ALTER TABLE stream_links ALTER COLUMN platform DROP DEFAULT;
+ALTER TABLE stream_links DROP CONSTRAINT twitch_links_pkey;
+ALTER TABLE stream_links ADD PRIMARY KEY (discord_user, platform);
@@ -0,0 +1,2 @@
+-- The initial migration only creates this column along with the table, which is a no-op on databases where `users` already existed.
+ALTER TABLE users ADD COLUMN IF NOT EXISTS data JSONB;
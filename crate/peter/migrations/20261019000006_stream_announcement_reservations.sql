@@ -0,0 +1,2 @@
+-- Announcements are reserved before they're sent so a failed insert can't cause a duplicate announcement. The message ID is filled in afterwards.
+ALTER TABLE stream_announcements ALTER COLUMN message DROP NOT NULL;
@@ -0,0 +1,8 @@
+-- On-demand team roles created by Peter, so resetting the teams only deletes these and never a role which merely has the same name.
+CREATE TABLE team_roles (
+    guild INT8 NOT NULL,
+    -- the team number, starting at 1
+    team INT2 NOT NULL,
+    role INT8 NOT NULL,
+    PRIMARY KEY (guild, team)
+);
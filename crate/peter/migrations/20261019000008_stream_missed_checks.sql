@@ -0,0 +1,2 @@
+-- The number of checks in a row a stream was missing from, see MISSED_CHECKS_UNTIL_ENDED in stream.rs.
+ALTER TABLE stream_announcements ADD COLUMN missed_checks INT2 NOT NULL DEFAULT 0;
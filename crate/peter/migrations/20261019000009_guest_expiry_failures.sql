@@ -0,0 +1,2 @@
+-- Guest access whose role Discord refused to remove is kept for an admin to handle instead of being retried.
+ALTER TABLE guests ADD COLUMN failed BOOLEAN NOT NULL DEFAULT FALSE;
@@ -1,7 +1,13 @@
 use {
-    std::collections::{
-        BTreeMap,
-        BTreeSet,
+    std::{
+        collections::{
+            BTreeMap,
+            BTreeSet,
+            HashMap,
+        },
+        env,
+        path::PathBuf,
+        sync::OnceLock,
     },
     serde::{
         Deserialize,
@@ -14,51 +20,451 @@ use {
     wheel::fs,
     crate::{
         Error,
+        quiz::TeamRole,
         twitch,
+        user_list,
+        welcome,
         werewolf,
+        youtube,
     },
 };
 
-const PATH: &str = "/usr/local/share/fidera/config.json";
+const DEFAULT_PATH: &str = "/usr/local/share/fidera/config.json";
 
-#[derive(Deserialize, Serialize)]
+const GEFOLGE: GuildId = GuildId::new(355761290809180170);
+const FENHL: UserId = UserId::new(86841168427495424);
+
+/// Set by the `--config` command-line option.
+static PATH_OVERRIDE: OnceLock<PathBuf> = OnceLock::new();
+
+/// Overrides the path of the config file for the rest of the process. Has no effect if it was already overridden.
+pub fn set_path(path: PathBuf) {
+    let _ = PATH_OVERRIDE.set(path);
+}
+
+/// Serializes changes to the config, so that a reload or another change can't be lost while a change is being saved.
+static UPDATE_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// Returns the path of the config file, which can be overridden using the `--config` command-line option or the `PETER_CONFIG` environment variable, e.g. for a staging instance.
+pub fn path() -> PathBuf {
+    if let Some(path) = PATH_OVERRIDE.get() { return path.clone() }
+    env::var_os("PETER_CONFIG").map_or_else(|| PathBuf::from(DEFAULT_PATH), PathBuf::from)
+}
+
+/// Returns the value of a secret given via the environment variable with the given name, or read from the file named by that variable with `_FILE` appended.
+async fn secret_from_env(var: &str) -> Result<Option<String>, Error> {
+    if let Some(value) = env::var_os(var) {
+        return Ok(Some(value.into_string().map_err(env::VarError::NotUnicode)?))
+    }
+    if let Some(path) = env::var_os(format!("{var}_FILE")) {
+        return Ok(Some(fs::read_to_string(path).await?.trim_end().to_owned()))
+    }
+    Ok(None)
+}
+
+#[derive(Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Config {
     pub channels: Channels,
+    /// Whether Peter uses its PostgreSQL database. Smaller deployments can set this to `false`, which makes features that store data there unavailable, e.g. polls, quizzes, guests and stream announcements. Changes only take effect after a restart.
+    #[serde(default = "default_database")]
+    pub database: bool,
+    /// Where errors are reported. Errors from `serenity_utils` itself, e.g. from the user list and voice state exports, only go to the first entry, which only changes after a restart.
+    #[serde(default = "default_error_notifiers")]
+    pub error_notifiers: Vec<ErrorTarget>,
+    #[serde(default = "default_guilds")]
+    pub guilds: BTreeMap<GuildId, GuildConfig>,
+    /// The guild whose members can sign into gefolge.org, and which IPC commands and Gefolge-specific slash commands apply to.
+    #[serde(default = "default_main_guild")]
+    pub main_guild: GuildId,
     pub peter: Peter,
     pub(crate) twitch: twitch::Config,
+    /// Where the member list of the main guild is exported to.
+    #[serde(default = "default_user_list")]
+    pub user_list: Vec<user_list::SinkConfig>,
     pub werewolf: BTreeMap<GuildId, werewolf::Config>,
+    /// If set, members' live streams on YouTube are announced.
+    #[serde(default)]
+    pub(crate) youtube: Option<youtube::Config>,
+    /// The config as it was loaded from the file, before secrets from environment variables were applied. Used by [`Config::save`] to find the keys which were changed.
+    #[serde(skip)]
+    loaded: serde_json::Value,
 }
 
 impl TypeMapKey for Config {
     type Value = Config;
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Channels {
     pub ignored: BTreeSet<ChannelId>,
     pub voice: ChannelId,
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Clone, Copy, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum ErrorTarget {
+    /// Errors are sent as direct messages to this user.
+    User {
+        user: UserId,
+    },
+    Channel {
+        channel: ChannelId,
+    },
+}
+
+impl From<ErrorTarget> for serenity_utils::builder::ErrorNotifier {
+    fn from(target: ErrorTarget) -> Self {
+        match target {
+            ErrorTarget::User { user } => Self::User(user),
+            ErrorTarget::Channel { channel } => Self::Channel(channel),
+        }
+    }
+}
+
+fn default_database() -> bool { true }
+
+fn default_error_notifiers() -> Vec<ErrorTarget> {
+    vec![ErrorTarget::User { user: FENHL }]
+}
+
+fn default_main_guild() -> GuildId { GEFOLGE }
+
+fn default_user_list() -> Vec<user_list::SinkConfig> {
+    vec![user_list::SinkConfig::Postgres]
+}
+
+/// Per-guild settings.
+#[derive(Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GuildConfig {
+    /// Members with this role can use admin commands.
+    #[serde(default)]
+    pub admin: Option<RoleId>,
+    /// Where admins are notified, e.g. about expired guest access.
+    #[serde(default)]
+    pub admin_channel: Option<ChannelId>,
+    /// The role for guests, i.e. people who aren't members of the Gefolge.
+    #[serde(default)]
+    pub guest: Option<RoleId>,
+    /// The role for members of the Gefolge.
+    #[serde(default)]
+    pub mensch: Option<RoleId>,
+    /// Members with this role can moderate quizzes and assign teams.
+    #[serde(default)]
+    pub quizmaster: Option<RoleId>,
+    /// The roles used for quiz teams. Team commands are only registered in guilds with at least one team.
+    #[serde(default)]
+    pub teams: Vec<TeamRole>,
+    /// How new members are greeted. If omitted, no welcome messages are sent.
+    #[serde(default)]
+    pub welcome: Option<welcome::Config>,
+}
+
+fn default_guilds() -> BTreeMap<GuildId, GuildConfig> {
+    BTreeMap::from([(GEFOLGE, GuildConfig {
+        admin: Some(RoleId::new(355776689051140099)),
+        admin_channel: None,
+        guest: Some(RoleId::new(784929665478557737)),
+        mensch: Some(RoleId::new(386753710434287626)),
+        quizmaster: Some(RoleId::new(847443327069454378)),
+        teams: [
+            828431321586991104,
+            828431500747735100,
+            828431624759935016,
+            828431736194072606,
+            828431741332750407,
+            828431913738960956,
+        ].into_iter().map(|role| TeamRole::Existing { role: RoleId::new(role) }).collect(),
+        welcome: None,
+    })])
+}
+
+#[derive(Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Peter {
+    /// Can be omitted if it's provided via the `PETER_BOT_TOKEN` or `PETER_BOT_TOKEN_FILE` environment variable.
+    #[serde(default)]
     pub bot_token: String,
     pub self_assignable_roles: BTreeSet<RoleId>,
+    /// Groups of self-assignable roles shown together by `/roles`.
+    #[serde(default)]
+    pub role_categories: Vec<RoleCategory>,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RoleCategory {
+    pub name: String,
+    /// If `true`, assigning a role from this category removes the member's other roles from it.
+    #[serde(default)]
+    pub exclusive: bool,
+    pub roles: Vec<RoleId>,
 }
 
 impl Config {
+    /// Whether the member has the role which `role` selects from their guild's config.
+    pub fn has_role(&self, member: &Member, role: impl FnOnce(&GuildConfig) -> Option<RoleId>) -> bool {
+        self.guilds.get(&member.guild_id).and_then(role).is_some_and(|role| member.roles.contains(&role))
+    }
+
+    /// Reads the config file, with secrets given via environment variables taking precedence over the ones in the file.
+    ///
+    /// Fails with a list of all problems found by [`Config::check`] if there are any. Warnings are printed to stderr.
     pub async fn new() -> Result<Config, Error> {
-        let buf = fs::read_to_string(PATH).await?;
-        Ok(serde_json::from_str(&buf)?) //TODO use async-json
+        let (config, warnings) = Self::load().await?;
+        for warning in &warnings {
+            eprintln!("{}: {warning}", path().display());
+        }
+        Ok(config)
+    }
+
+    /// Like [`Config::new`], but returns the warnings found by [`Config::check`] instead of printing them.
+    async fn load() -> Result<(Config, Vec<String>), Error> {
+        let buf = fs::read_to_string(path()).await?;
+        let (mut config, warnings) = match Self::check(&buf) {
+            (Some(config), problems, warnings) if problems.is_empty() => (config, warnings),
+            (_, problems, _) => return Err(Error::InvalidConfig(problems)),
+        };
+        config.loaded = serde_json::to_value(&config)?;
+        if let Some(bot_token) = secret_from_env("PETER_BOT_TOKEN").await? {
+            config.peter.bot_token = bot_token;
+        }
+        if let Some(client_secret) = secret_from_env("PETER_TWITCH_CLIENT_SECRET").await? {
+            config.twitch.client_secret = client_secret;
+        }
+        if let Some(ref mut youtube) = config.youtube {
+            if let Some(api_key) = secret_from_env("PETER_YOUTUBE_API_KEY").await? {
+                youtube.api_key = api_key;
+            }
+            if youtube.api_key.is_empty() { return Err(Error::MissingSecret("YouTube API key")) }
+        }
+        if config.peter.bot_token.is_empty() { return Err(Error::MissingSecret("bot token")) }
+        if config.twitch.client_secret.is_empty() { return Err(Error::MissingSecret("Twitch client secret")) }
+        Ok((config, warnings))
+    }
+
+    /// Parses the contents of a config file and checks it for problems that can be found without connecting to Discord.
+    ///
+    /// Returns the config if it could be parsed, along with a human-readable description of each problem and each warning.
+    /// Warnings, such as unknown keys, don't prevent Peter from starting, but are treated as problems by `peter check-config`.
+    pub fn check(buf: &str) -> (Option<Config>, Vec<String>, Vec<String>) {
+        let config = match serde_json::from_str::<Config>(buf) { //TODO use async-json
+            Ok(config) => config,
+            // includes line and column, as well as invalid snowflakes such as 0
+            Err(e) => return (None, vec![e.to_string()], Vec::default()),
+        };
+        let mut problems = Vec::default();
+        if config.error_notifiers.is_empty() {
+            problems.push(format!("errorNotifiers: at least one user or channel is required"));
+        }
+        if !config.database && config.user_list.iter().any(|sink| matches!(sink, user_list::SinkConfig::Postgres)) {
+            problems.push(format!("userList: the postgres sink requires the database, which is disabled"));
+        }
+        // serde ignores unknown keys, which are usually typos of optional keys
+        let mut warnings = Vec::default();
+        if let (Ok(file), Ok(known)) = (serde_json::from_str(buf), serde_json::to_value(&config)) {
+            unknown_keys("", &file, &known, &mut warnings);
+        }
+        let mut categories = HashMap::<_, Vec<_>>::default();
+        for category in &config.peter.role_categories {
+            for &role in &category.roles {
+                categories.entry(role).or_default().push(&category.name[..]);
+            }
+        }
+        for (role, categories) in categories {
+            if categories.len() > 1 {
+                problems.push(format!("peter.roleCategories: role {role} is in multiple categories: {}", categories.join(", ")));
+            }
+        }
+        for (guild, werewolf) in &config.werewolf {
+            if werewolf.voice_channel == Some(werewolf.text_channel) {
+                problems.push(format!("werewolf.{guild}: textChannel and voiceChannel are the same channel"));
+            }
+        }
+        (Some(config), problems, warnings)
+    }
+
+    /// Checks that the channels and roles referenced for the given guild exist. Called once the guild is available.
+    pub async fn check_guild(&self, ctx: &Context, guild: GuildId) -> Result<Vec<String>, Error> {
+        let channels = guild.channels(ctx).await?;
+        let roles = guild.roles(ctx).await?;
+        let channel_problem = |key: &str, channel: ChannelId| (!channels.contains_key(&channel)).then(|| format!("{key}: no channel with ID {channel} in server {guild}"));
+        let role_problem = |key: &str, role: RoleId| (!roles.contains_key(&role)).then(|| format!("{key}: no role with ID {role} in server {guild}"));
+        let mut problems = Vec::default();
+        if guild == self.main_guild {
+            problems.extend(channel_problem("twitch.channel", self.twitch.channel));
+            problems.extend(role_problem("twitch.role", self.twitch.role));
+            if let Some(ref youtube) = self.youtube {
+                problems.extend(channel_problem("youtube.channel", youtube.channel));
+                problems.extend(role_problem("youtube.role", youtube.role));
+            }
+            problems.extend(channel_problem("channels.voice", self.channels.voice));
+            problems.extend(self.peter.self_assignable_roles.iter().filter_map(|&role| role_problem("peter.selfAssignableRoles", role)));
+            for category in &self.peter.role_categories {
+                problems.extend(category.roles.iter().filter_map(|&role| role_problem(&format!("peter.roleCategories.{}", category.name), role)));
+            }
+        }
+        if let Some(guild_config) = self.guilds.get(&guild) {
+            problems.extend(guild_config.admin_channel.and_then(|admin_channel| channel_problem(&format!("guilds.{guild}.adminChannel"), admin_channel)));
+            for (key, role) in [("admin", guild_config.admin), ("guest", guild_config.guest), ("mensch", guild_config.mensch), ("quizmaster", guild_config.quizmaster)] {
+                problems.extend(role.and_then(|role| role_problem(&format!("guilds.{guild}.{key}"), role)));
+            }
+            for (idx, team) in guild_config.teams.iter().enumerate() {
+                if let TeamRole::Existing { role } = *team {
+                    problems.extend(role_problem(&format!("guilds.{guild}.teams[{idx}]"), role));
+                }
+            }
+            problems.extend(guild_config.welcome.as_ref().and_then(|welcome| welcome.channel).and_then(|channel| channel_problem(&format!("guilds.{guild}.welcome.channel"), channel)));
+        }
+        if let Some(werewolf) = self.werewolf.get(&guild) {
+            problems.extend(role_problem(&format!("werewolf.{guild}.role"), werewolf.role));
+            problems.extend(channel_problem(&format!("werewolf.{guild}.textChannel"), werewolf.text_channel));
+            problems.extend(werewolf.voice_channel.and_then(|voice_channel| channel_problem(&format!("werewolf.{guild}.voiceChannel"), voice_channel)));
+        }
+        Ok(problems)
     }
 
-    /*
-    pub(crate) async fn save(self) -> Result<(), Error> {
-        let buf = serde_json::to_vec(&self)?; //TODO use async-json
-        File::create(PATH).await?.write_all(&buf).await?;
+    /// Reads the config file again and replaces the config in the Serenity context.
+    ///
+    /// If the file can't be read or isn't a valid config, an error is returned and the old config stays in effect. Otherwise, returns the warnings found by [`Config::check`].
+    pub async fn reload(ctx: &Context) -> Result<Vec<String>, Error> {
+        let _lock = UPDATE_LOCK.lock().await;
+        let (config, warnings) = Self::load().await?;
+        *ctx.data.write().await.get_mut::<Config>().ok_or(Error::MissingConfig)? = config;
+        Ok(warnings)
+    }
+
+    /// Changes the config using `f` and writes it to disk if `f` returns `true`.
+    ///
+    /// The change is made to a copy, which only replaces the config in the Serenity context once it was saved, so the config in memory never differs from the one on disk.
+    pub async fn update(ctx: &Context, f: impl FnOnce(&mut Config) -> bool) -> Result<bool, Error> {
+        let _lock = UPDATE_LOCK.lock().await;
+        let mut config = ctx.data.read().await.get::<Config>().ok_or(Error::MissingConfig)?.clone();
+        if !f(&mut config) { return Ok(false) }
+        config.save().await?;
+        *ctx.data.write().await.get_mut::<Config>().ok_or(Error::MissingConfig)? = config;
+        Ok(true)
+    }
+
+    /// Writes the config back to disk.
+    ///
+    /// Only the keys which were changed since the file was loaded are updated, so the file keeps its formatting of everything else, edits made to the file in the meantime are kept, and defaults which were omitted stay omitted.
+    /// Secrets are written as they appear in the file, so that secrets given via environment variables don't end up on disk.
+    async fn save(&mut self) -> Result<(), Error> {
+        let path = path();
+        let mut file = serde_json::from_str::<serde_json::Value>(&fs::read_to_string(&path).await?)?;
+        let mut after = serde_json::to_value(&*self)?;
+        for (section, key) in [("peter", "botToken"), ("twitch", "clientSecret"), ("youtube", "apiKey")] {
+            // optional sections are serialized as null if omitted
+            let Some(after_section) = after[section].as_object_mut() else { continue };
+            match self.loaded.get(section).and_then(|loaded_section| loaded_section.get(key)) {
+                Some(loaded_secret) => { after_section.insert(key.to_owned(), loaded_secret.clone()); }
+                None => { after_section.remove(key); }
+            }
+        }
+        patch(&mut file, &self.loaded, &after);
+        let buf = serde_json::to_vec_pretty(&file)?; //TODO use async-json
+        // written to a temporary file first and renamed over the config, so a crash can't leave a partially written config behind
+        let mut tmp_path = path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        fs::write(&tmp_path, buf).await?;
+        tokio::fs::rename(&tmp_path, &path).await?;
+        self.loaded = after;
         Ok(())
     }
-    */
+}
+
+/// Applies the differences between the serialized configs `before` and `after` to the parsed config file, leaving everything else as it is in the file.
+fn patch(file: &mut serde_json::Value, before: &serde_json::Value, after: &serde_json::Value) {
+    match (file, before, after) {
+        (serde_json::Value::Object(file), serde_json::Value::Object(before), serde_json::Value::Object(after)) => {
+            for (key, after_value) in after {
+                match (file.get_mut(key), before.get(key)) {
+                    (Some(file_value), Some(before_value)) => patch(file_value, before_value, after_value),
+                    (_, before_value) => if before_value != Some(after_value) {
+                        file.insert(key.clone(), after_value.clone());
+                    },
+                }
+            }
+            for key in before.keys() {
+                if !after.contains_key(key) {
+                    file.remove(key);
+                }
+            }
+        }
+        (file, before, after) => if before != after {
+            *file = after.clone();
+        },
+    }
+}
+
+/// Adds a warning for each key in `file` that doesn't appear in the re-serialized config `known`.
+fn unknown_keys(path: &str, file: &serde_json::Value, known: &serde_json::Value, warnings: &mut Vec<String>) {
+    match (file, known) {
+        (serde_json::Value::Object(file), serde_json::Value::Object(known)) => for (key, value) in file {
+            let path = if path.is_empty() { key.clone() } else { format!("{path}.{key}") };
+            if let Some(known_value) = known.get(key) {
+                unknown_keys(&path, value, known_value, warnings);
+            } else {
+                warnings.push(format!("{path}: unknown key"));
+            }
+        },
+        (serde_json::Value::Array(file), serde_json::Value::Array(known)) => for (idx, (value, known_value)) in file.iter().zip(known).enumerate() {
+            unknown_keys(&format!("{path}[{idx}]"), value, known_value, warnings);
+        },
+        (_, _) => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(extra: &str) -> String {
+        format!(r#"{{
+            "channels": {{"ignored": [], "voice": "1"}},
+            "peter": {{"selfAssignableRoles": ["2", "3"]{extra}}},
+            "twitch": {{"clientID": "abc"}},
+            "werewolf": {{}}
+        }}"#)
+    }
+
+    #[test]
+    fn valid() {
+        let (config, problems, warnings) = Config::check(&config(""));
+        assert!(config.is_some());
+        assert!(problems.is_empty(), "{problems:?}");
+        assert!(warnings.is_empty(), "{warnings:?}");
+    }
+
+    #[test]
+    fn unknown_key_warnings() {
+        let (_, problems, mut warnings) = Config::check(&config(r#", "typo": 1, "roleCategories": [{"name": "Spiele", "roles": ["2"], "exclusiv": true}]"#));
+        assert!(problems.is_empty(), "{problems:?}");
+        warnings.sort();
+        assert_eq!(warnings, ["peter.roleCategories[0].exclusiv: unknown key", "peter.typo: unknown key"]);
+    }
+
+    #[test]
+    fn missing_error_notifiers() {
+        let (_, problems, _) = Config::check(&config("").replacen('{', r#"{"errorNotifiers": [], "#, 1));
+        assert_eq!(problems, ["errorNotifiers: at least one user or channel is required"]);
+    }
+
+    #[test]
+    fn duplicate_role_category() {
+        let (_, problems, _) = Config::check(&config(r#", "roleCategories": [{"name": "Spiele", "roles": ["2"]}, {"name": "Farben", "roles": ["2", "3"]}]"#));
+        assert_eq!(problems, ["peter.roleCategories: role 2 is in multiple categories: Spiele, Farben"]);
+    }
+
+    #[test]
+    fn patch_preserves_untouched_keys() {
+        let mut file = serde_json::json!({"peter": {"selfAssignableRoles": ["2"], "typo": 1}, "werewolf": {}});
+        let before = serde_json::json!({"peter": {"botToken": "", "selfAssignableRoles": ["2"]}, "werewolf": {}});
+        let after = serde_json::json!({"peter": {"botToken": "", "selfAssignableRoles": ["2", "3"]}, "werewolf": {}});
+        patch(&mut file, &before, &after);
+        assert_eq!(file, serde_json::json!({"peter": {"selfAssignableRoles": ["2", "3"], "typo": 1}, "werewolf": {}}));
+    }
 }
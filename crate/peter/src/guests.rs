@@ -0,0 +1,125 @@
+//! Time-limited guest access and promotion of guests to full members.
+
+use {
+    std::{
+        convert::Infallible as Never,
+        time::Duration,
+    },
+    chrono::prelude::*,
+    futures::future,
+    serenity::{
+        http::StatusCode,
+        model::prelude::*,
+        prelude::*,
+        utils::MessageBuilder,
+    },
+    serenity_utils::RwFuture,
+    sqlx::PgPool,
+    tokio::time::sleep,
+    crate::{
+        Database,
+        Error,
+        config::{
+            Config,
+            GuildConfig,
+        },
+        user_list,
+    },
+};
+
+/// The maximum length of guest access in days, to keep the expiry time within range.
+pub const MAX_DAYS: u64 = 366;
+
+/// Returns the role which `role` selects from the guild's config.
+async fn role(ctx: &Context, guild: GuildId, name: &'static str, role: impl FnOnce(&GuildConfig) -> Option<RoleId>) -> Result<RoleId, Error> {
+    ctx.data.read().await.get::<Config>().expect("missing config").guilds.get(&guild).and_then(role).ok_or(Error::RoleNotConfigured(name))
+}
+
+/// Gives the user the guest role until the given time. If the user is already a guest, the expiry is replaced.
+pub async fn grant(ctx: &Context, pool: &PgPool, guild: GuildId, user: UserId, granted_by: UserId, expires_at: DateTime<Utc>) -> Result<(), Error> {
+    let guest = role(ctx, guild, "guest", |guild_config| guild_config.guest).await?;
+    // the expiry is saved first so the role is never given without being removed later
+    sqlx::query!("INSERT INTO guests (guild, member, granted_by, expires_at) VALUES ($1, $2, $3, $4) ON CONFLICT (guild, member) DO UPDATE SET granted_by = EXCLUDED.granted_by, granted_at = NOW(), expires_at = EXCLUDED.expires_at, failed = FALSE",
+        guild.get() as i64,
+        user.get() as i64,
+        granted_by.get() as i64,
+        expires_at,
+    ).execute(pool).await?;
+    ctx.http.add_member_role(guild, user, guest, Some("Gastzugang")).await?;
+    user_list::upsert(ctx, &guild.member(ctx, user).await?).await
+}
+
+/// Replaces the user's guest role with the Mensch role and ends their guest access.
+///
+/// Returns `Ok(Err(_))` with a message for the admin if the user isn't a guest.
+pub async fn promote(ctx: &Context, pool: &PgPool, guild: GuildId, user: UserId) -> Result<Result<(), &'static str>, Error> {
+    let guest = role(ctx, guild, "guest", |guild_config| guild_config.guest).await?;
+    let mensch = role(ctx, guild, "mensch", |guild_config| guild_config.mensch).await?;
+    let has_guest_role = guild.member(ctx, user).await?.roles.contains(&guest);
+    let has_guest_access = sqlx::query_scalar!(r#"SELECT EXISTS (SELECT 1 FROM guests WHERE guild = $1 AND member = $2) AS "exists!""#, guild.get() as i64, user.get() as i64).fetch_one(pool).await?;
+    if !has_guest_role && !has_guest_access { return Ok(Err("diese Person ist kein Gast")) }
+    ctx.http.add_member_role(guild, user, mensch, Some("zum Mensch befördert")).await?;
+    ctx.http.remove_member_role(guild, user, guest, Some("zum Mensch befördert")).await?;
+    sqlx::query!("DELETE FROM guests WHERE guild = $1 AND member = $2", guild.get() as i64, user.get() as i64).execute(pool).await?;
+    user_list::upsert(ctx, &guild.member(ctx, user).await?).await?;
+    Ok(Ok(()))
+}
+
+/// Removes the guest role from a guest whose access has expired and notifies the guild's admin channel.
+async fn expire_one(ctx: &Context, pool: &PgPool, guild: GuildId, user: UserId) -> Result<(), Error> {
+    let (guest, admin_channel) = {
+        let data = ctx.data.read().await;
+        let guild_config = data.get::<Config>().expect("missing config").guilds.get(&guild);
+        (guild_config.and_then(|guild_config| guild_config.guest), guild_config.and_then(|guild_config| guild_config.admin_channel))
+    };
+    if let Some(guest) = guest {
+        match guild.member(ctx, user).await {
+            Ok(member) => {
+                member.remove_role(ctx, guest).await?;
+                user_list::upsert(ctx, &guild.member(ctx, user).await?).await?;
+            }
+            // the guest already left the guild
+            Err(serenity::Error::Http(HttpError::UnsuccessfulRequest(response))) if response.status_code == StatusCode::NOT_FOUND => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    sqlx::query!("DELETE FROM guests WHERE guild = $1 AND member = $2", guild.get() as i64, user.get() as i64).execute(pool).await?;
+    println!("guest access for {user} in {guild} expired");
+    if let Some(admin_channel) = admin_channel {
+        admin_channel.say(ctx, MessageBuilder::default().push("Gastzugang abgelaufen: ").mention(&user).build()).await?;
+    }
+    Ok(())
+}
+
+/// Removes the guest role from guests whose access has expired and notifies the guild's admin channel.
+///
+/// A guest whose access can't be removed doesn't prevent the others from being processed.
+/// If Discord rejects the request, e.g. because Peter lacks permissions, the error is reported and the guest's entry is marked as failed instead of retrying forever. Granting guest access again clears the mark.
+pub async fn expire(ctx_fut: RwFuture<Context>) -> Result<Never, Error> {
+    let pool = {
+        let ctx = ctx_fut.read().await;
+        let data = (*ctx).data.read().await;
+        data.get::<Database>().expect("missing database connection").clone()
+    };
+    // without a database, there are no guests
+    let Some(pool) = pool else { return future::pending().await };
+    loop {
+        {
+            let ctx = ctx_fut.read().await;
+            for row in sqlx::query!("SELECT guild, member FROM guests WHERE expires_at <= NOW() AND NOT failed").fetch_all(&pool).await? {
+                let guild = GuildId::new(row.guild as u64);
+                let user = UserId::new(row.member as u64);
+                match expire_one(&*ctx, &pool, guild, user).await {
+                    Ok(()) => {}
+                    Err(e) if matches!(&e, Error::Serenity(serenity::Error::Http(HttpError::UnsuccessfulRequest(response))) if response.status_code.is_client_error()) => {
+                        eprintln!("failed to expire guest access for {user} in {guild}, marking it as failed: {e} ({e:?})");
+                        sqlx::query!("UPDATE guests SET failed = TRUE WHERE guild = $1 AND member = $2", row.guild, row.member).execute(&pool).await?;
+                        crate::notify_error(&ctx, &format!("guest access expiry for {user} in {guild}"), &e).await?;
+                    }
+                    Err(e) => eprintln!("failed to expire guest access for {user} in {guild}, will retry: {e} ({e:?})"),
+                }
+            }
+        }
+        sleep(Duration::from_secs(60)).await;
+    }
+}
@@ -2,20 +2,26 @@ use {
     std::iter,
     serenity::{
         all::EditMember,
+        model::prelude::*,
         prelude::*,
     },
-    crate::GEFOLGE,
+    crate::config::Config,
 };
 
+async fn main_guild(ctx: &Context) -> GuildId {
+    ctx.data.read().await.get::<Config>().expect("missing config").main_guild
+}
+
 serenity_utils::ipc! {
     use serenity::model::prelude::*;
 
     const PORT: u16 = 18807;
 
-    /// Adds the given role to the given user. No-op if the user already has the role.
+    /// Adds the given role to the given user in the main guild. No-op if the user already has the role.
     async fn add_role(ctx: &Context, user: UserId, role: RoleId) -> Result<(), String> {
-        let roles = iter::once(role).chain(GEFOLGE.member(ctx, user).await.map_err(|e| format!("failed to get member data: {e}"))?.roles.into_iter());
-        GEFOLGE.edit_member(ctx, user, EditMember::default().roles(roles)).await.map_err(|e| format!("failed to edit roles: {e}"))?;
+        let guild = main_guild(ctx).await;
+        let roles = iter::once(role).chain(guild.member(ctx, user).await.map_err(|e| format!("failed to get member data: {e}"))?.roles.into_iter());
+        guild.edit_member(ctx, user, EditMember::default().roles(roles)).await.map_err(|e| format!("failed to edit roles: {e}"))?;
         Ok(())
     }
 
@@ -34,18 +40,30 @@ serenity_utils::ipc! {
         Ok(())
     }
 
+    /// Reloads the config file. If the new config is invalid, the old one stays in effect.
+    ///
+    /// Slash commands are not re-registered, use `/reload-config` if the change affects which commands are available.
+    /// Changes to the bot token and to `twitch.eventSub` only take effect after a restart.
+    async fn reload_config(ctx: &Context) -> Result<(), String> {
+        let warnings = Config::reload(ctx).await.map_err(|e| format!("failed to reload config: {e}"))?;
+        for warning in warnings {
+            eprintln!("{}: {warning}", crate::config::path().display());
+        }
+        Ok(())
+    }
+
     /// Shuts down the bot and cleanly exits the program.
     async fn quit(ctx: &Context) -> Result<(), String> {
         serenity_utils::shut_down(&ctx).await;
         Ok(())
     }
 
-    /// Changes the display name for the given user in the Gefolge guild to the given string.
+    /// Changes the display name for the given user in the main guild to the given string.
     ///
     /// If the given string is equal to the user's username, the display name will instead be removed.
     async fn set_display_name(ctx: &Context, user_id: UserId, new_display_name: String) -> Result<(), String> {
         let user = user_id.to_user(ctx).await.map_err(|e| format!("failed to get user for set-display-name: {e}"))?;
-        match GEFOLGE.edit_member(ctx, &user, EditMember::default().nickname(if user.name == new_display_name { "" } else { &new_display_name })).await {
+        match main_guild(ctx).await.edit_member(ctx, &user, EditMember::default().nickname(if user.name == new_display_name { "" } else { &new_display_name })).await {
             Ok(_) => Ok(()),
             Err(serenity::Error::Http(e)) => if let HttpError::UnsuccessfulRequest(response) = e {
                 Err(format!("failed to set display name: {response:?}"))
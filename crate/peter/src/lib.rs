@@ -7,35 +7,63 @@ use {
         io,
     },
     serenity::{
-        model::prelude::*,
         prelude::*,
+        utils::MessageBuilder,
     },
     sqlx::PgPool,
     wheel::traits::IsNetworkError,
 };
 
 pub mod config;
+pub mod guests;
 pub mod ipc;
 pub mod lang;
 pub mod parse;
+pub mod poll;
+pub mod quiz;
+pub mod roles;
+pub mod stream;
 pub mod twitch;
 pub mod user_list;
+pub mod welcome;
 pub mod werewolf;
+pub mod youtube;
 
-pub const GEFOLGE: GuildId = GuildId::new(355761290809180170);
+/// `typemap` key for the PostgreSQL database connection, which is `None` if the database is disabled in the config.
+pub struct Database;
 
-pub const ADMIN: RoleId = RoleId::new(355776689051140099);
-pub const QUIZMASTER: RoleId = RoleId::new(847443327069454378);
-pub const MENSCH: RoleId = RoleId::new(386753710434287626);
-pub const GUEST: RoleId = RoleId::new(784929665478557737);
+impl TypeMapKey for Database {
+    type Value = Option<PgPool>;
+}
 
-pub const FENHL: UserId = UserId::new(86841168427495424);
+/// Returns the database connection, or [`Error::NoDatabase`] if the database is disabled in the config.
+pub fn database(data: &TypeMap) -> Result<PgPool, Error> {
+    data.get::<Database>().expect("missing database connection").clone().ok_or(Error::NoDatabase)
+}
 
-/// `typemap` key for the PostgreSQL database connection.
-pub struct Database;
+/// Creates or updates the tables Peter uses, using the migrations in the `migrations` directory of this crate.
+pub async fn migrate(pool: &PgPool) -> Result<(), Error> {
+    sqlx::migrate!().run(pool).await?;
+    Ok(())
+}
 
-impl TypeMapKey for Database {
-    type Value = PgPool;
+/// Reports an error which can't be returned from an event handler, e.g. because the handler's signature doesn't allow for errors.
+pub async fn notify_error(ctx: &Context, source: &str, e: &Error) -> serenity::Result<()> {
+    let msg = MessageBuilder::default()
+        .push("error in ")
+        .push_safe(source)
+        .push(": ")
+        .push_safe(e.to_string())
+        .push_codeblock_safe(format!("{e:?}"), None)
+        .build();
+    let data = ctx.data.read().await;
+    for target in &data.get::<config::Config>().expect("missing config").error_notifiers {
+        match *target {
+            config::ErrorTarget::Channel { channel } => { channel.say(ctx, &msg).await?; }
+            config::ErrorTarget::User { user } => { user.create_dm_channel(ctx).await?.say(ctx, &msg).await?; }
+        }
+    }
+    Ok(())
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -44,13 +72,21 @@ pub enum Error {
     #[error(transparent)] Io(#[from] io::Error),
     #[error(transparent)] Ipc(#[from] ipc::Error),
     #[error(transparent)] Json(#[from] serde_json::Error),
+    #[error(transparent)] Migrate(#[from] sqlx::migrate::MigrateError),
+    #[error(transparent)] Reqwest(#[from] reqwest::Error),
     #[error(transparent)] QwwStartGame(#[from] quantum_werewolf::game::state::StartGameError),
     #[error(transparent)] Serenity(#[from] serenity::Error),
     #[error(transparent)] Sql(#[from] sqlx::Error),
     #[error(transparent)] Twitch(#[from] twitch_helix::Error),
     #[error(transparent)] Wheel(#[from] wheel::Error),
+    /// A slash command option was missing or had an unexpected type.
+    #[error("missing or malformed slash command option: {0}")]
+    CommandOption(&'static str),
     #[error("invalid game action: {0}")]
     GameAction(String),
+    /// The config file could not be parsed or has other problems.
+    #[error("invalid config: {}", .0.join("; "))]
+    InvalidConfig(Vec<String>),
     /// Returned if the config is not present in Serenity context.
     #[error("config missing in Serenity context")]
     MissingConfig,
@@ -60,8 +96,30 @@ pub enum Error {
     /// The reply to an IPC command did not end in a newline.
     #[error("the reply to an IPC command did not end in a newline")]
     MissingNewline,
-    #[error("Twitch returned unexpected user info")]
-    TwitchUserLookup,
+    /// A secret was neither in the config file nor given via an environment variable.
+    #[error("missing {0} in config")]
+    MissingSecret(&'static str),
+    /// A feature which stores data in the database was used while the database is disabled in the config.
+    #[error("the database is disabled in the config")]
+    NoDatabase,
+    /// A team command was used in a guild without configured teams.
+    #[error("no teams are configured for this server")]
+    NoTeams,
+    /// A command that only works in guilds was used elsewhere.
+    #[error("this command only works in a server")]
+    NotInGuild,
+    /// A streaming platform was used which isn't configured.
+    #[error("{0} is not configured")]
+    PlatformNotConfigured(&'static str),
+    /// A command needs a role which isn't configured for the guild.
+    #[error("no {0} role is configured for this server")]
+    RoleNotConfigured(&'static str),
+    /// Received an interaction for a slash command that Peter doesn't know about.
+    #[error("unknown slash command: /{0}")]
+    UnknownCommand(String),
+    /// Received an interaction for a message component that Peter doesn't know about.
+    #[error("unknown message component: {0}")]
+    UnknownComponent(String),
 }
 
 impl IsNetworkError for Error {
@@ -70,15 +128,26 @@ impl IsNetworkError for Error {
             | Self::Env(_)
             | Self::Ipc(_)
             | Self::Json(_)
+            | Self::Migrate(_)
             | Self::QwwStartGame(_)
             | Self::Sql(_)
+            | Self::CommandOption(_)
             | Self::GameAction(_)
+            | Self::InvalidConfig(_)
             | Self::MissingConfig
             | Self::MissingContext
             | Self::MissingNewline
-            | Self::TwitchUserLookup
+            | Self::MissingSecret(_)
+            | Self::NoDatabase
+            | Self::NoTeams
+            | Self::NotInGuild
+            | Self::PlatformNotConfigured(_)
+            | Self::RoleNotConfigured(_)
+            | Self::UnknownCommand(_)
+            | Self::UnknownComponent(_)
                 => false,
             Self::Io(e) => e.is_network_error(),
+            Self::Reqwest(e) => e.is_network_error(),
             Self::Serenity(e) => match e {
                 serenity::Error::Http(HttpError::Request(e)) => e.is_request() || e.is_connect() || e.is_timeout() || e.status().is_some_and(|status| status.is_server_error()),
                 serenity::Error::Io(e) => e.is_network_error(),
@@ -0,0 +1,352 @@
+//! Reaction and button based polls. Polls are stored in the database so they can be closed after a restart.
+
+use {
+    std::{
+        collections::{
+            HashMap,
+            HashSet,
+        },
+        convert::Infallible as Never,
+        time::Duration,
+    },
+    chrono::prelude::*,
+    futures::future,
+    itertools::Itertools as _,
+    serenity::{
+        all::{
+            CreateActionRow,
+            CreateButton,
+            CreateEmbed,
+            CreateInteractionResponse,
+            CreateInteractionResponseMessage,
+            CreateMessage,
+            EditMessage,
+        },
+        http::StatusCode,
+        model::prelude::*,
+        prelude::*,
+        utils::MessageBuilder,
+    },
+    serenity_utils::RwFuture,
+    sqlx::PgPool,
+    tokio::time::sleep,
+    crate::{
+        Database,
+        Error,
+        config::Config,
+    },
+};
+
+/// The maximum number of options per poll, limited by the number of different reactions Discord allows on a message.
+pub const MAX_OPTIONS: usize = 20;
+
+/// Discord's limit on the length of button labels.
+const MAX_LABEL_LEN: usize = 80;
+
+/// The maximum length of the question, leaving room for the `Ergebnis: ` prefix within Discord's 256-character limit on embed titles.
+pub const MAX_QUESTION_LEN: u16 = 240;
+
+/// The maximum length of the semicolon-separated options, leaving room for vote counts within Discord's limit on embed descriptions.
+pub const MAX_OPTIONS_LEN: u16 = 1000;
+
+/// Discord's limit on the length of embed descriptions.
+const MAX_DESCRIPTION_LEN: usize = 4096;
+
+/// The maximum duration of a poll in minutes, about a year.
+pub const MAX_DURATION_MINUTES: u64 = 366 * 24 * 60;
+
+pub struct Poll {
+    pub question: String,
+    pub options: Vec<String>,
+    /// Whether each voter may choose more than one option.
+    pub multi: bool,
+    /// Whether the results list only the number of votes for each option. Anonymous polls always use buttons.
+    pub anonymous: bool,
+    /// Whether votes are cast using buttons rather than reactions.
+    pub buttons: bool,
+    pub deadline: Option<DateTime<Utc>>,
+}
+
+impl Poll {
+    fn embed(&self, closed: bool) -> CreateEmbed {
+        let mut description = MessageBuilder::default();
+        for (idx, option) in self.options.iter().enumerate() {
+            description.push(option_emoji(idx).to_string()).push(" ").push_line_safe(option);
+        }
+        description.push_line("");
+        description.push(if self.multi { "Mehrfachauswahl möglich" } else { "eine Stimme pro Person" });
+        if self.anonymous {
+            description.push(", anonym");
+        }
+        if closed {
+            description.push(". Diese Umfrage ist beendet.");
+        } else {
+            description.push(if self.buttons { ", Abstimmung per Button" } else { ", Abstimmung per Reaktion" });
+            if let Some(deadline) = self.deadline {
+                description.push(format!(", endet <t:{}:R>", deadline.timestamp()));
+            }
+        }
+        CreateEmbed::default()
+            .title(&self.question)
+            .description(description.build())
+    }
+
+    fn components(&self) -> Vec<CreateActionRow> {
+        let mut rows = Vec::default();
+        if self.buttons {
+            for chunk in &self.options.iter().enumerate().chunks(5) {
+                rows.push(CreateActionRow::Buttons(chunk.map(|(idx, option)| CreateButton::new(format!("poll:vote:{idx}"))
+                    .style(ButtonStyle::Secondary)
+                    .emoji(option_emoji(idx))
+                    .label(option.chars().take(MAX_LABEL_LEN).collect::<String>())
+                ).collect()));
+            }
+        }
+        rows.push(CreateActionRow::Buttons(vec![CreateButton::new("poll:close")
+            .style(ButtonStyle::Danger)
+            .label("Umfrage beenden")
+        ]));
+        rows
+    }
+
+    fn results_embed(&self, votes: &[Vec<UserId>], invalid: usize) -> CreateEmbed {
+        let num_voters = votes.iter().flatten().unique().count();
+        let lines = self.options.iter().zip(votes).enumerate().map(|(idx, (option, voters))| {
+            let mut line = MessageBuilder::default();
+            line.push(option_emoji(idx).to_string()).push(" ").push_bold_safe(option);
+            line.push(format!(": {} {}", voters.len(), if voters.len() == 1 { "Stimme" } else { "Stimmen" }));
+            if num_voters > 0 {
+                line.push(format!(" ({}%)", (voters.len() * 100 + num_voters / 2) / num_voters));
+            }
+            line.build()
+        }).collect_vec();
+        let mut footer = format!("{num_voters} {} insgesamt", if num_voters == 1 { "Person hat abgestimmt" } else { "Personen haben abgestimmt" });
+        if invalid > 0 {
+            footer.push_str(&format!(", {invalid} {} mit mehreren Reaktionen wurden nicht gezählt", if invalid == 1 { "Stimme" } else { "Stimmen" }));
+        }
+        // the space left for voter mentions is shared equally between the options that have any
+        let num_listed = if self.anonymous { 0 } else { votes.iter().filter(|voters| !voters.is_empty()).count() };
+        let used = lines.iter().map(|line| line.chars().count() + 1).sum::<usize>() + footer.chars().count();
+        let budget = MAX_DESCRIPTION_LEN.saturating_sub(used).checked_div(num_listed).unwrap_or_default();
+        let mut description = String::default();
+        for (line, voters) in lines.into_iter().zip(votes) {
+            description.push_str(&line);
+            if !self.anonymous && !voters.is_empty() {
+                description.push_str(&voter_list(voters, budget));
+            }
+            description.push('\n');
+        }
+        description.push_str(&footer);
+        CreateEmbed::default()
+            .title(format!("Ergebnis: {}", self.question))
+            .description(description)
+    }
+}
+
+/// Mentions the voters, followed by the number of those who don't fit within `budget` characters.
+fn voter_list(voters: &[UserId], budget: usize) -> String {
+    // enough room for the longest possible count of omitted voters
+    let reserved = format!(" +{} weitere", voters.len()).chars().count();
+    let mut list = String::from(" — ");
+    for (idx, voter) in voters.iter().enumerate() {
+        let mention = format!("{}{}", if idx > 0 { ", " } else { "" }, voter.mention());
+        let fits_all = idx + 1 == voters.len() && list.chars().count() + mention.chars().count() <= budget;
+        if !fits_all && list.chars().count() + mention.chars().count() + reserved > budget {
+            list.push_str(&format!(" +{} weitere", voters.len() - idx));
+            return list
+        }
+        list.push_str(&mention);
+    }
+    list
+}
+
+/// The regional indicator symbols 🇦 to 🇹 are used to label the options.
+fn option_emoji(idx: usize) -> ReactionType {
+    assert!(idx < MAX_OPTIONS, "poll option index out of range");
+    ReactionType::Unicode(char::from_u32(0x1f1e6 + idx as u32).expect("regional indicator symbols are valid chars").to_string())
+}
+
+/// Posts a new poll in the given channel.
+pub async fn create(ctx: &Context, pool: &PgPool, channel: ChannelId, author: UserId, poll: Poll) -> Result<(), Error> {
+    let msg = channel.send_message(ctx, CreateMessage::default()
+        .add_embed(poll.embed(false))
+        .components(poll.components())
+    ).await?;
+    sqlx::query!("INSERT INTO polls
+        (message, channel, author, question, options, multi, anonymous, buttons, deadline)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+    ",
+        msg.id.get() as i64,
+        channel.get() as i64,
+        author.get() as i64,
+        &poll.question,
+        &poll.options,
+        poll.multi,
+        poll.anonymous,
+        poll.buttons,
+        poll.deadline,
+    ).execute(pool).await?;
+    if !poll.buttons {
+        for idx in 0..poll.options.len() {
+            msg.react(ctx, option_emoji(idx)).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Counts the reactions of a reaction poll, unless `poll.buttons` is set and `votes` already contains the votes, then closes the poll message and posts the results as a reply.
+async fn post_results(ctx: &Context, channel: ChannelId, message: MessageId, poll: &Poll, mut votes: Vec<Vec<UserId>>) -> serenity::Result<()> {
+    let mut invalid = 0;
+    if !poll.buttons {
+        for (idx, voters) in votes.iter_mut().enumerate() {
+            let mut after = None;
+            loop {
+                let page = channel.reaction_users(ctx, message, option_emoji(idx), Some(100), after).await?;
+                after = page.last().map(|user| user.id);
+                let done = page.len() < 100;
+                voters.extend(page.into_iter().filter(|user| !user.bot).map(|user| user.id));
+                if done { break }
+            }
+        }
+        if !poll.multi {
+            // reactions can't be limited to one per user, so votes from anyone who reacted with multiple options are ignored
+            let mut counts = HashMap::<_, usize>::default();
+            for &voter in votes.iter().flatten() {
+                *counts.entry(voter).or_default() += 1;
+            }
+            let multi_voters = counts.into_iter().filter_map(|(voter, count)| (count > 1).then_some(voter)).collect::<HashSet<_>>();
+            invalid = multi_voters.len();
+            for voters in &mut votes {
+                voters.retain(|voter| !multi_voters.contains(voter));
+            }
+        }
+    }
+    channel.edit_message(ctx, message, EditMessage::new()
+        .embed(poll.embed(true))
+        .components(Vec::default())
+    ).await?;
+    channel.send_message(ctx, CreateMessage::default()
+        .reference_message((channel, message))
+        .add_embed(poll.results_embed(&votes, invalid))
+    ).await?;
+    Ok(())
+}
+
+/// Closes the poll posted as the given message and posts its results. No-op if the poll is already closed.
+///
+/// The poll is only marked as closed once the results have been posted, so it's retried if posting them fails, unless the poll message was deleted.
+pub async fn close(ctx: &Context, pool: &PgPool, message: MessageId) -> Result<(), Error> {
+    let mut transaction = pool.begin().await?;
+    // the row stays locked until the results are posted so the poll can't be closed twice concurrently
+    let Some(row) = sqlx::query!("SELECT channel, question, options, multi, anonymous, buttons, deadline FROM polls WHERE message = $1 AND NOT closed FOR UPDATE", message.get() as i64).fetch_optional(&mut *transaction).await? else { return Ok(()) };
+    let channel = ChannelId::new(row.channel as u64);
+    let poll = Poll {
+        question: row.question,
+        options: row.options,
+        multi: row.multi,
+        anonymous: row.anonymous,
+        buttons: row.buttons,
+        deadline: row.deadline,
+    };
+    let mut votes = vec![Vec::default(); poll.options.len()];
+    if poll.buttons {
+        for row in sqlx::query!("SELECT voter, option FROM poll_votes WHERE poll = $1 ORDER BY voter", message.get() as i64).fetch_all(&mut *transaction).await? {
+            if let Some(voters) = votes.get_mut(row.option as usize) {
+                voters.push(UserId::new(row.voter as u64));
+            }
+        }
+    }
+    match post_results(ctx, channel, message, &poll, votes).await {
+        Ok(()) => {}
+        // the poll message or its channel was deleted, so there's nowhere to post the results
+        Err(serenity::Error::Http(HttpError::UnsuccessfulRequest(response))) if response.status_code == StatusCode::NOT_FOUND => {}
+        Err(e) => return Err(e.into()),
+    }
+    sqlx::query!("UPDATE polls SET closed = TRUE WHERE message = $1", message.get() as i64).execute(&mut *transaction).await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// Handles the vote and close buttons of polls.
+pub async fn handle_component(ctx: &Context, interaction: &ComponentInteraction) -> Result<(), Error> {
+    let pool = crate::database(&*ctx.data.read().await)?;
+    let message = interaction.message.id;
+    let Some(poll) = sqlx::query!("SELECT author, options, multi, closed FROM polls WHERE message = $1", message.get() as i64).fetch_optional(&pool).await? else {
+        return Err(Error::UnknownComponent(interaction.data.custom_id.clone()))
+    };
+    let response = if poll.closed {
+        format!("diese Umfrage ist schon beendet")
+    } else if interaction.data.custom_id == "poll:close" {
+        let is_admin = if let Some(ref member) = interaction.member {
+            ctx.data.read().await.get::<Config>().expect("missing config").has_role(member, |guild| guild.admin)
+        } else {
+            false
+        };
+        if interaction.user.id.get() as i64 == poll.author || is_admin {
+            // closing may take a while for reaction polls, so the interaction is acknowledged first
+            interaction.create_response(ctx, CreateInteractionResponse::Acknowledge).await?;
+            close(ctx, &pool, message).await?;
+            return Ok(())
+        } else {
+            format!("nur die Person, die die Umfrage erstellt hat, kann sie beenden")
+        }
+    } else {
+        let option = interaction.data.custom_id.strip_prefix("poll:vote:")
+            .and_then(|option| option.parse::<i16>().ok())
+            .filter(|&option| usize::try_from(option).is_ok_and(|option| option < poll.options.len()))
+            .ok_or_else(|| Error::UnknownComponent(interaction.data.custom_id.clone()))?;
+        let voter = interaction.user.id.get() as i64;
+        let mut transaction = pool.begin().await?;
+        let already_voted = sqlx::query_scalar!(r#"SELECT EXISTS (SELECT 1 FROM poll_votes WHERE poll = $1 AND voter = $2 AND option = $3) AS "exists!""#, message.get() as i64, voter, option).fetch_one(&mut *transaction).await?;
+        if already_voted {
+            sqlx::query!("DELETE FROM poll_votes WHERE poll = $1 AND voter = $2 AND option = $3", message.get() as i64, voter, option).execute(&mut *transaction).await?;
+        } else {
+            if !poll.multi {
+                sqlx::query!("DELETE FROM poll_votes WHERE poll = $1 AND voter = $2", message.get() as i64, voter).execute(&mut *transaction).await?;
+            }
+            sqlx::query!("INSERT INTO poll_votes (poll, voter, option) VALUES ($1, $2, $3)", message.get() as i64, voter, option).execute(&mut *transaction).await?;
+        }
+        let votes = sqlx::query_scalar!("SELECT option FROM poll_votes WHERE poll = $1 AND voter = $2 ORDER BY option", message.get() as i64, voter).fetch_all(&mut *transaction).await?;
+        transaction.commit().await?;
+        if votes.is_empty() {
+            format!("du hast keine Stimme abgegeben")
+        } else {
+            let mut builder = MessageBuilder::default();
+            builder.push(if votes.len() == 1 { "deine Stimme: " } else { "deine Stimmen: " });
+            for (idx, option) in votes.into_iter().enumerate() {
+                if idx > 0 { builder.push(", "); }
+                builder.push(option_emoji(option as usize).to_string()).push(" ").push_safe(&poll.options[option as usize]);
+            }
+            builder.build()
+        }
+    };
+    interaction.create_response(ctx, CreateInteractionResponse::Message(CreateInteractionResponseMessage::new()
+        .ephemeral(true)
+        .content(response)
+    )).await?;
+    Ok(())
+}
+
+/// Closes polls whose deadline has passed.
+///
+/// A poll which can't be closed, e.g. because its message was deleted, doesn't prevent the others from being closed and is retried later.
+pub async fn close_expired(ctx_fut: RwFuture<Context>) -> Result<Never, Error> {
+    let pool = {
+        let ctx = ctx_fut.read().await;
+        let data = (*ctx).data.read().await;
+        data.get::<Database>().expect("missing database connection").clone()
+    };
+    // without a database, there are no polls
+    let Some(pool) = pool else { return future::pending().await };
+    loop {
+        {
+            let ctx = ctx_fut.read().await;
+            for message in sqlx::query_scalar!("SELECT message FROM polls WHERE NOT closed AND deadline <= NOW()").fetch_all(&pool).await? {
+                if let Err(e) = close(&*ctx, &pool, MessageId::new(message as u64)).await {
+                    eprintln!("failed to close poll {message}: {e} ({e:?})");
+                }
+            }
+        }
+        sleep(Duration::from_secs(30)).await;
+    }
+}
@@ -0,0 +1,351 @@
+//! Quiz mode: the quizmaster opens rounds, teams buzz in or submit answers, and points are tracked on a scoreboard.
+
+use {
+    std::{
+        collections::{
+            BTreeMap,
+            HashMap,
+        },
+        pin::pin,
+    },
+    futures::stream::TryStreamExt as _,
+    itertools::Itertools as _,
+    serde::{
+        Deserialize,
+        Serialize,
+    },
+    serenity::{
+        all::{
+            CreateEmbed,
+            CreateMessage,
+            EditMember,
+            EditMessage,
+            EditRole,
+        },
+        http::StatusCode,
+        model::prelude::*,
+        prelude::*,
+        utils::MessageBuilder,
+    },
+    sqlx::PgPool,
+    tokio::sync::Mutex,
+    crate::{
+        Error,
+        config::Config,
+    },
+};
+
+/// Discord's limit on the length of nicknames.
+const MAX_NICKNAME_LEN: usize = 32;
+
+/// A role representing a team, as configured in [`crate::config::GuildConfig::teams`].
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum TeamRole {
+    /// A role which already exists in the guild.
+    Existing {
+        role: RoleId,
+    },
+    /// A role which Peter creates when someone joins the team and deletes when the teams are reset.
+    OnDemand {
+        name: String,
+        #[serde(default)]
+        colour: Option<u32>,
+    },
+}
+
+/// Returns the number of teams configured for the given guild.
+pub fn num_teams(config: &Config, guild: GuildId) -> usize {
+    config.guilds.get(&guild).map_or(0, |guild_config| guild_config.teams.len())
+}
+
+/// Serializes the creation of on-demand team roles, since concurrent `/team` commands could otherwise create the same role twice.
+static ROLE_CREATION_LOCK: Mutex<()> = Mutex::const_new(());
+
+/// The team roles of a guild along with the IDs of the roles that currently exist.
+pub struct Teams {
+    guild: GuildId,
+    config: Vec<TeamRole>,
+    roles: Vec<Option<RoleId>>,
+}
+
+impl Teams {
+    /// Returns the teams configured for the given guild, or `None` if the guild has no teams.
+    ///
+    /// On-demand roles are looked up in the `team_roles` table rather than by name, so roles Peter didn't create are never used or deleted.
+    pub async fn get(ctx: &Context, guild: GuildId) -> Result<Option<Self>, Error> {
+        let (config, pool) = {
+            let data = ctx.data.read().await;
+            (
+                data.get::<Config>().expect("missing config").guilds.get(&guild).map(|guild_config| guild_config.teams.clone()).unwrap_or_default(),
+                crate::database(&data)?,
+            )
+        };
+        if config.is_empty() { return Ok(None) }
+        let mut created = HashMap::new();
+        if config.iter().any(|team| matches!(team, TeamRole::OnDemand { .. })) {
+            let guild_roles = guild.roles(ctx).await?;
+            for row in sqlx::query!("SELECT team, role FROM team_roles WHERE guild = $1", guild.get() as i64).fetch_all(&pool).await? {
+                let role = RoleId::new(row.role as u64);
+                if guild_roles.contains_key(&role) {
+                    created.insert(usize::try_from(row.team - 1).expect("team numbers start at 1"), role);
+                } else {
+                    // the role was deleted by hand, so it's created again when needed
+                    sqlx::query!("DELETE FROM team_roles WHERE guild = $1 AND team = $2 AND role = $3", guild.get() as i64, row.team, row.role).execute(&pool).await?;
+                }
+            }
+        }
+        let roles = config.iter().enumerate().map(|(idx, team)| match team {
+            TeamRole::Existing { role } => Some(*role),
+            TeamRole::OnDemand { .. } => created.get(&idx).copied(),
+        }).collect();
+        Ok(Some(Self { guild, config, roles }))
+    }
+
+    pub fn len(&self) -> usize {
+        self.config.len()
+    }
+
+    /// Returns the index of the team the given member is in, if any.
+    pub fn team_of(&self, member: &Member) -> Option<usize> {
+        self.roles.iter().position(|role| role.is_some_and(|role| member.roles.contains(&role)))
+    }
+
+    /// Returns the role for the given team, creating it if necessary.
+    async fn role(&mut self, ctx: &Context, pool: &PgPool, team: usize) -> Result<RoleId, Error> {
+        if let Some(role) = self.roles[team] { return Ok(role) }
+        let TeamRole::OnDemand { ref name, colour } = self.config[team] else { unreachable!("existing team roles are always resolved") };
+        let _lock = ROLE_CREATION_LOCK.lock().await;
+        // another command may have created the role since the teams were looked up
+        let role = if let Some(role) = sqlx::query_scalar!("SELECT role FROM team_roles WHERE guild = $1 AND team = $2", self.guild.get() as i64, team as i16 + 1).fetch_optional(pool).await? {
+            RoleId::new(role as u64)
+        } else {
+            let mut builder = EditRole::new().name(name).mentionable(true);
+            if let Some(colour) = colour {
+                builder = builder.colour(colour);
+            }
+            let role = self.guild.create_role(ctx, builder).await?.id;
+            sqlx::query!("INSERT INTO team_roles (guild, team, role) VALUES ($1, $2, $3)", self.guild.get() as i64, team as i16 + 1, role.get() as i64).execute(pool).await?;
+            role
+        };
+        self.roles[team] = Some(role);
+        Ok(role)
+    }
+}
+
+/// Moves the member into the given team and prefixes their nickname with the team number.
+///
+/// The member's original nickname is stored in the database so it can be restored by [`reset_teams`].
+pub async fn join_team(ctx: &Context, pool: &PgPool, teams: &mut Teams, member: &Member, team: usize) -> Result<(), Error> {
+    let role = teams.role(ctx, pool, team).await?;
+    member.remove_roles(ctx, &teams.roles.iter().enumerate().filter_map(|(idx, &role_id)| role_id.filter(|_| idx != team)).collect_vec()).await?;
+    member.add_role(ctx, role).await?;
+    sqlx::query!("INSERT INTO team_nicknames (guild, member, nick) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING",
+        member.guild_id.get() as i64,
+        member.user.id.get() as i64,
+        member.nick.as_ref(),
+    ).execute(pool).await?;
+    let original_nick = sqlx::query_scalar!("SELECT nick FROM team_nicknames WHERE guild = $1 AND member = $2", member.guild_id.get() as i64, member.user.id.get() as i64).fetch_one(pool).await?;
+    let name = original_nick.as_deref().or(member.user.global_name.as_deref()).unwrap_or(&member.user.name);
+    let nick = format!("[Team {}] {name}", team + 1).chars().take(MAX_NICKNAME_LEN).collect::<String>();
+    set_nickname(ctx, member.guild_id, member.user.id, &nick).await
+}
+
+/// Removes the quizmaster and team roles from everyone in the guild, deletes on-demand team roles, and restores the nicknames changed by [`join_team`].
+pub async fn reset_teams(ctx: &Context, pool: &PgPool, guild: GuildId) -> Result<(), Error> {
+    let mut roles = ctx.data.read().await.get::<Config>().expect("missing config").guilds.get(&guild).and_then(|guild_config| guild_config.quizmaster).into_iter().collect_vec();
+    if let Some(teams) = Teams::get(ctx, guild).await? {
+        roles.extend(teams.config.iter().filter_map(|team| if let TeamRole::Existing { role } = *team { Some(role) } else { None }));
+    }
+    // roles created by Peter are deleted rather than removed from each member, regardless of whether they're still configured
+    for row in sqlx::query!("SELECT team, role FROM team_roles WHERE guild = $1", guild.get() as i64).fetch_all(pool).await? {
+        match guild.delete_role(ctx, RoleId::new(row.role as u64)).await {
+            Ok(()) => {}
+            Err(serenity::Error::Http(HttpError::UnsuccessfulRequest(response))) if response.status_code == StatusCode::NOT_FOUND => {}
+            Err(e) => return Err(e.into()),
+        }
+        sqlx::query!("DELETE FROM team_roles WHERE guild = $1 AND team = $2", guild.get() as i64, row.team).execute(pool).await?;
+    }
+    let mut members = pin!(guild.members_iter(ctx));
+    while let Some(member) = members.try_next().await? {
+        member.remove_roles(&ctx, &roles).await?;
+    }
+    for row in sqlx::query!("SELECT member, nick FROM team_nicknames WHERE guild = $1", guild.get() as i64).fetch_all(pool).await? {
+        set_nickname(ctx, guild, UserId::new(row.member as u64), row.nick.as_deref().unwrap_or_default()).await?;
+        sqlx::query!("DELETE FROM team_nicknames WHERE guild = $1 AND member = $2", guild.get() as i64, row.member).execute(pool).await?;
+    }
+    Ok(())
+}
+
+/// Changes the member's nickname, removing it if the given nickname is empty.
+///
+/// Members Peter isn't allowed to rename, such as the server owner, are skipped.
+async fn set_nickname(ctx: &Context, guild: GuildId, user: UserId, nick: &str) -> Result<(), Error> {
+    match guild.edit_member(ctx, user, EditMember::default().nickname(nick)).await {
+        Ok(_) => Ok(()),
+        Err(serenity::Error::Http(HttpError::UnsuccessfulRequest(response))) if response.status_code == StatusCode::FORBIDDEN => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// A running quiz. Also serves as `typemap` key for the quizzes running in each guild.
+pub struct Quiz {
+    pub channel: ChannelId,
+    scoreboard: MessageId,
+    /// Points per team index.
+    scores: BTreeMap<usize, i64>,
+    rounds: u32,
+    round: Option<Round>,
+}
+
+impl TypeMapKey for Quiz {
+    type Value = HashMap<GuildId, Quiz>;
+}
+
+struct Round {
+    /// Team indices in the order they buzzed in.
+    buzzes: Vec<usize>,
+    answers: BTreeMap<usize, String>,
+}
+
+impl Quiz {
+    /// Starts a quiz in the given channel by posting its scoreboard.
+    pub async fn start(ctx: &Context, channel: ChannelId) -> Result<Self, Error> {
+        let scores = BTreeMap::default();
+        let scoreboard = channel.send_message(ctx, CreateMessage::default().add_embed(scoreboard_embed(&scores, false))).await?;
+        Ok(Self {
+            scoreboard: scoreboard.id,
+            rounds: 0,
+            round: None,
+            channel, scores,
+        })
+    }
+
+    /// Deletes the scoreboard of a quiz which was started but never used, e.g. because another quiz was started at the same time.
+    pub async fn discard(self, ctx: &Context) -> Result<(), Error> {
+        self.channel.delete_message(ctx, self.scoreboard).await?;
+        Ok(())
+    }
+
+    /// Opens a new round, discarding any buzzes and answers from the previous one.
+    pub fn open_round(&mut self, question: Option<&str>) -> Update {
+        self.rounds += 1;
+        self.round = Some(Round {
+            buzzes: Vec::default(),
+            answers: BTreeMap::default(),
+        });
+        let mut builder = MessageBuilder::default();
+        builder.push_bold(format!("Runde {}", self.rounds));
+        if let Some(question) = question {
+            builder.push(": ").push_safe(question);
+        }
+        Update::Say(self.channel, builder.build())
+    }
+
+    /// Closes the current round and returns the message listing the submitted answers, or `None` if no round was open.
+    pub fn close_round(&mut self) -> Option<Update> {
+        let round = self.round.take()?;
+        let mut builder = MessageBuilder::default();
+        builder.push_bold(format!("Runde {} ist beendet.", self.rounds));
+        if !round.buzzes.is_empty() {
+            builder.push_line("").push("Gebuzzert haben: ").push(round.buzzes.iter().map(|&team| format!("Team {}", team + 1)).join(", "));
+        }
+        if round.answers.is_empty() {
+            builder.push_line("").push("Es wurden keine Antworten abgegeben.");
+        } else {
+            for (team, answer) in round.answers {
+                builder.push_line("").push_bold(format!("Team {}", team + 1)).push(": ").push_safe(answer);
+            }
+        }
+        Some(Update::Say(self.channel, builder.build()))
+    }
+
+    /// Records a buzz from the given team. Returns the team's position in the buzz order, or `None` if no round is open or the team already buzzed.
+    pub fn buzz(&mut self, team: usize) -> Option<usize> {
+        let round = self.round.as_mut()?;
+        if round.buzzes.contains(&team) { return None }
+        round.buzzes.push(team);
+        Some(round.buzzes.len())
+    }
+
+    /// Records the given team's answer, replacing any previous answer from that team. Returns `false` if no round is open.
+    pub fn submit_answer(&mut self, team: usize, answer: String) -> bool {
+        if let Some(ref mut round) = self.round {
+            round.answers.insert(team, answer);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Adds the given number of points (which may be negative) to the team's score. Returns the new score and the scoreboard update.
+    pub fn award(&mut self, team: usize, points: i64) -> (i64, Update) {
+        let score = self.scores.entry(team).or_default();
+        *score += points;
+        (*score, Update::Scoreboard(self.channel, self.scoreboard, scoreboard_embed(&self.scores, false)))
+    }
+
+    /// Ends the quiz, posting the final standings and saving them to the database.
+    ///
+    /// The quiz must already have been removed from the `typemap`, so this doesn't hold any lock.
+    pub async fn end(mut self, ctx: &Context, pool: &PgPool, guild: GuildId) -> Result<(), Error> {
+        if let Some(update) = self.close_round() {
+            update.send(ctx).await?;
+        }
+        let mut transaction = pool.begin().await?;
+        let id = sqlx::query_scalar!("INSERT INTO quizzes (guild, channel, rounds) VALUES ($1, $2, $3) RETURNING id",
+            guild.get() as i64,
+            self.channel.get() as i64,
+            self.rounds as i32,
+        ).fetch_one(&mut *transaction).await?;
+        for (&team, &points) in &self.scores {
+            sqlx::query!("INSERT INTO quiz_scores (quiz, team, points) VALUES ($1, $2, $3)", id, team as i16 + 1, points).execute(&mut *transaction).await?;
+        }
+        transaction.commit().await?;
+        self.channel.edit_message(ctx, self.scoreboard, EditMessage::new().embed(scoreboard_embed(&self.scores, true))).await?;
+        self.channel.send_message(ctx, CreateMessage::default()
+            .content("Das Quiz ist vorbei!")
+            .reference_message((self.channel, self.scoreboard))
+        ).await?;
+        Ok(())
+    }
+}
+
+/// A change to a quiz's messages. The quiz state is updated while the `typemap` is locked, and the update is sent to Discord after releasing the lock.
+#[must_use]
+pub enum Update {
+    Say(ChannelId, String),
+    Scoreboard(ChannelId, MessageId, CreateEmbed),
+}
+
+impl Update {
+    pub async fn send(self, ctx: &Context) -> Result<(), Error> {
+        match self {
+            Self::Say(channel, text) => { channel.say(ctx, text).await?; }
+            Self::Scoreboard(channel, message, embed) => { channel.edit_message(ctx, message, EditMessage::new().embed(embed)).await?; }
+        }
+        Ok(())
+    }
+}
+
+fn scoreboard_embed(scores: &BTreeMap<usize, i64>, is_final: bool) -> CreateEmbed {
+    let description = if scores.is_empty() {
+        format!("Noch keine Punkte vergeben.")
+    } else {
+        let mut standings = scores.iter().collect_vec();
+        standings.sort_by_key(|&(&team, &points)| (-points, team));
+        let mut rank = 0;
+        let mut last_points = None;
+        standings.into_iter().enumerate().map(|(idx, (&team, &points))| {
+            // teams with equal scores share a rank
+            if last_points != Some(points) {
+                rank = idx + 1;
+                last_points = Some(points);
+            }
+            format!("{rank}. Team {}: {points} {}", team + 1, if points == 1 { "Punkt" } else { "Punkte" })
+        }).join("\n")
+    };
+    CreateEmbed::default()
+        .title(if is_final { "Endstand" } else { "Punktestand" })
+        .description(description)
+}
@@ -0,0 +1,214 @@
+//! Self-assignable roles, grouped into categories which can be mutually exclusive.
+
+use {
+    itertools::Itertools as _,
+    serenity::{
+        all::{
+            CreateActionRow,
+            CreateButton,
+            CreateEmbed,
+            CreateInteractionResponse,
+            CreateInteractionResponseFollowup,
+            CreateInteractionResponseMessage,
+            CreateMessage,
+        },
+        model::prelude::*,
+        prelude::*,
+        utils::MessageBuilder,
+    },
+    crate::{
+        Error,
+        config::{
+            Config,
+            Peter,
+        },
+    },
+};
+
+/// The maximum number of buttons on a message, which is also the maximum number of autocomplete choices.
+const MAX_BUTTONS: usize = 25;
+
+/// Discord's limit on the length of button labels.
+const MAX_LABEL_LEN: usize = 80;
+
+/// A group of self-assignable roles which is shown as one message by `/roles`.
+pub struct Category {
+    pub name: String,
+    /// Whether members may have at most one role from this category.
+    pub exclusive: bool,
+    pub roles: Vec<RoleId>,
+}
+
+/// Returns the configured role categories, followed by an implicit category for self-assignable roles that aren't in any category.
+///
+/// Roles listed in a category are self-assignable even if they're missing from [`Peter::self_assignable_roles`].
+pub fn categories(config: &Peter) -> Vec<Category> {
+    let mut categories = config.role_categories.iter().map(|category| Category {
+        name: category.name.clone(),
+        exclusive: category.exclusive,
+        roles: category.roles.clone(),
+    }).collect_vec();
+    let uncategorized = config.self_assignable_roles.iter()
+        .copied()
+        .filter(|role| !config.role_categories.iter().any(|category| category.roles.contains(role)))
+        .collect_vec();
+    if !uncategorized.is_empty() {
+        categories.push(Category {
+            name: if config.role_categories.is_empty() { format!("Rollen") } else { format!("Sonstige Rollen") },
+            exclusive: false,
+            roles: uncategorized,
+        });
+    }
+    categories
+}
+
+async fn category_of(ctx: &Context, role: RoleId) -> Option<Category> {
+    categories(&ctx.data.read().await.get::<Config>().expect("missing config").peter).into_iter().find(|category| category.roles.contains(&role))
+}
+
+/// Adds a self-assignable role to the member, removing other roles of the same category if it's exclusive.
+///
+/// Returns `Ok(Err(_))` with a message for the member if the role can't be added.
+pub async fn add(ctx: &Context, member: &Member, role: RoleId) -> Result<Result<(), &'static str>, Error> {
+    let Some(category) = category_of(ctx, role).await else { return Ok(Err("diese Rolle ist nicht selbstzuweisbar")) };
+    if member.roles.contains(&role) { return Ok(Err("du hast diese Rolle schon")) }
+    if category.exclusive {
+        let others = category.roles.into_iter().filter(|&other| other != role && member.roles.contains(&other)).collect_vec();
+        if !others.is_empty() {
+            member.remove_roles(ctx, &others).await?;
+        }
+    }
+    member.add_role(ctx, role).await?;
+    Ok(Ok(()))
+}
+
+/// Removes a self-assignable role from the member.
+///
+/// Returns `Ok(Err(_))` with a message for the member if the role can't be removed.
+pub async fn remove(ctx: &Context, member: &Member, role: RoleId) -> Result<Result<(), &'static str>, Error> {
+    if category_of(ctx, role).await.is_none() { return Ok(Err("diese Rolle ist nicht selbstzuweisbar")) }
+    if !member.roles.contains(&role) { return Ok(Err("du hast diese Rolle sowieso nicht")) }
+    member.remove_role(ctx, role).await?;
+    Ok(Ok(()))
+}
+
+/// Finds the self-assignable role the member meant, either by the ID which autocompletion fills in or by its name if they typed it out.
+///
+/// Returns `None` if there's no such self-assignable role.
+pub async fn find(ctx: &Context, guild: GuildId, text: &str) -> Result<Option<RoleId>, Error> {
+    let self_assignable = categories(&ctx.data.read().await.get::<Config>().expect("missing config").peter).into_iter().flat_map(|category| category.roles).collect_vec();
+    if let Ok(role) = text.parse() {
+        if self_assignable.contains(&role) { return Ok(Some(role)) }
+    }
+    let guild_roles = guild.roles(ctx).await?;
+    Ok(self_assignable.into_iter().find(|role| guild_roles.get(role).is_some_and(|role| role.name.eq_ignore_ascii_case(text.trim()))))
+}
+
+/// Returns the names and IDs of the self-assignable roles whose name contains the text entered so far, for slash command autocompletion.
+///
+/// If `has` is `Some`, only roles which the member has (`true`) or doesn't have (`false`) are suggested.
+pub async fn autocomplete(ctx: &Context, guild: GuildId, member: &Member, has: Option<bool>, partial: &str) -> Result<Vec<(String, String)>, Error> {
+    let guild_roles = guild.roles(ctx).await?;
+    let partial = partial.to_lowercase();
+    Ok(categories(&ctx.data.read().await.get::<Config>().expect("missing config").peter).into_iter()
+        .flat_map(|category| category.roles)
+        .filter(|role| has.map_or(true, |has| member.roles.contains(role) == has))
+        .filter_map(|role| guild_roles.get(&role))
+        .filter(|role| role.name.to_lowercase().contains(&partial))
+        .take(MAX_BUTTONS)
+        .map(|role| (role.name.clone(), role.id.to_string()))
+        .collect())
+}
+
+/// The custom ID of the button on welcome messages which shows the role menu to the member who clicked it.
+pub const MENU_BUTTON: &str = "role:menu";
+
+/// Returns an embed and the button rows for each role category. Categories with more roles than fit on one message are split across several.
+async fn menu_parts(ctx: &Context, guild: GuildId) -> Result<Vec<(CreateEmbed, Vec<CreateActionRow>)>, Error> {
+    let guild_roles = guild.roles(ctx).await?;
+    let categories = categories(&ctx.data.read().await.get::<Config>().expect("missing config").peter);
+    Ok(categories.into_iter().flat_map(|category| {
+        let roles = category.roles.iter().filter_map(|role| guild_roles.get(role)).collect_vec();
+        let pages = roles.len().div_ceil(MAX_BUTTONS);
+        roles.chunks(MAX_BUTTONS).enumerate().map(|(page, roles)| (
+            CreateEmbed::default()
+                .title(if pages > 1 { format!("{} ({}/{pages})", category.name, page + 1) } else { category.name.clone() })
+                .description(if category.exclusive {
+                    "Klicke auf eine Rolle, um sie zu bekommen oder loszuwerden. Du kannst nur eine dieser Rollen gleichzeitig haben."
+                } else {
+                    "Klicke auf eine Rolle, um sie zu bekommen oder loszuwerden."
+                }),
+            roles.chunks(5).map(|chunk| CreateActionRow::Buttons(chunk.iter().map(|role| CreateButton::new(format!("role:{}", role.id))
+                .style(ButtonStyle::Secondary)
+                .label(role.name.chars().take(MAX_LABEL_LEN).collect::<String>())
+            ).collect())).collect(),
+        )).collect_vec()
+    }).collect())
+}
+
+/// Returns one message per role category with a button for each role.
+pub async fn menu(ctx: &Context, guild: GuildId) -> Result<Vec<CreateMessage>, Error> {
+    Ok(menu_parts(ctx, guild).await?.into_iter().map(|(embed, components)| CreateMessage::default()
+        .add_embed(embed)
+        .components(components)
+    ).collect())
+}
+
+/// Shows the role menu as ephemeral messages to the member who clicked [`MENU_BUTTON`].
+async fn show_menu(ctx: &Context, interaction: &ComponentInteraction, guild: GuildId) -> Result<(), Error> {
+    let mut parts = menu_parts(ctx, guild).await?.into_iter();
+    let Some((embed, components)) = parts.next() else {
+        interaction.create_response(ctx, CreateInteractionResponse::Message(CreateInteractionResponseMessage::new()
+            .ephemeral(true)
+            .content("auf diesem Server gibt es keine selbstzuweisbaren Rollen")
+        )).await?;
+        return Ok(())
+    };
+    interaction.create_response(ctx, CreateInteractionResponse::Message(CreateInteractionResponseMessage::new()
+        .ephemeral(true)
+        .add_embed(embed)
+        .components(components)
+    )).await?;
+    for (embed, components) in parts {
+        interaction.create_followup(ctx, CreateInteractionResponseFollowup::new()
+            .ephemeral(true)
+            .add_embed(embed)
+            .components(components)
+        ).await?;
+    }
+    Ok(())
+}
+
+/// Handles the buttons of the messages posted by `/roles`, toggling the role for the member who clicked, as well as [`MENU_BUTTON`].
+pub async fn handle_component(ctx: &Context, interaction: &ComponentInteraction) -> Result<(), Error> {
+    if interaction.data.custom_id == MENU_BUTTON {
+        return show_menu(ctx, interaction, interaction.guild_id.ok_or(Error::NotInGuild)?).await
+    }
+    let role = interaction.data.custom_id.strip_prefix("role:")
+        .and_then(|role| role.parse::<RoleId>().ok())
+        .ok_or_else(|| Error::UnknownComponent(interaction.data.custom_id.clone()))?;
+    let guild = interaction.guild_id.ok_or(Error::NotInGuild)?;
+    let member = interaction.member.as_ref().ok_or(Error::NotInGuild)?;
+    let had_role = member.roles.contains(&role);
+    let result = if had_role {
+        remove(ctx, member, role).await?
+    } else {
+        add(ctx, member, role).await?
+    };
+    let response = match result {
+        Ok(()) => {
+            let mut builder = MessageBuilder::default();
+            builder.push(if had_role { "Rolle entfernt: " } else { "Rolle hinzugefügt: " });
+            if let Some(role) = guild.roles(ctx).await?.get(&role) {
+                builder.push_safe(&role.name);
+            }
+            builder.build()
+        }
+        Err(response) => response.to_owned(),
+    };
+    interaction.create_response(ctx, CreateInteractionResponse::Message(CreateInteractionResponseMessage::new()
+        .ephemeral(true)
+        .content(response)
+    )).await?;
+    Ok(())
+}
@@ -0,0 +1,315 @@
+//! Announcing members' live streams, independently of the streaming platform.
+
+use {
+    std::{
+        collections::{
+            BTreeMap,
+            HashMap,
+        },
+        convert::Infallible as Never,
+        future::Future,
+        pin::Pin,
+        sync::Arc,
+        time::Duration,
+    },
+    chrono::{
+        TimeDelta,
+        prelude::*,
+    },
+    futures::future,
+    itertools::Itertools as _,
+    serenity::{
+        all::{
+            CreateEmbed,
+            CreateMessage,
+            EditMessage,
+        },
+        http::StatusCode,
+        model::prelude::*,
+        prelude::*,
+        utils::MessageBuilder,
+    },
+    serenity_utils::RwFuture,
+    sqlx::PgPool,
+    tokio::{
+        sync::Notify,
+        time::{
+            sleep,
+            timeout,
+        },
+    },
+    crate::{
+        Database,
+        Error,
+        config::Config,
+    },
+};
+
+/// How often [`alerts`] checks whether a platform which isn't configured was added by reloading the config.
+const UNCONFIGURED_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How many checks in a row a stream has to be missing from before it's considered ended, since platforms sometimes briefly stop listing a live stream.
+const MISSED_CHECKS_UNTIL_ENDED: i16 = 2;
+
+/// Where and how streams on a platform are announced.
+#[derive(Clone, Copy)]
+pub struct Announcements {
+    pub channel: ChannelId,
+    /// The role which is pinged when a stream is announced.
+    pub role: RoleId,
+    /// If set, announcements are deleted this many minutes after the stream ends.
+    pub delete_after: Option<u32>,
+}
+
+/// An account on a streaming platform, as found by [`StreamProvider::resolve`].
+pub struct Account {
+    /// The platform's stable ID for the account, which is stored in the database.
+    pub id: String,
+    pub display_name: String,
+}
+
+/// A stream which is currently live.
+pub struct LiveStream {
+    /// The ID of the account which is streaming.
+    pub account: String,
+    /// Identifies the stream, so it's announced only once even if Peter restarts.
+    pub id: String,
+    pub title: String,
+    /// What's being streamed, e.g. the game. Not all platforms provide this.
+    pub category: Option<String>,
+    pub url: String,
+    pub started_at: DateTime<Utc>,
+}
+
+/// A streaming platform on which members' streams can be announced.
+pub trait StreamProvider: Sized + Send + Sync {
+    /// Identifies the platform in the database and in slash command options.
+    const PLATFORM: &'static str;
+    /// The platform's name as shown to users.
+    const NAME: &'static str;
+    /// The colour of announcement embeds.
+    const COLOUR: (u8, u8, u8);
+
+    /// Sets up the provider from the config and other data in the Serenity context. Returns `None` if the platform isn't configured.
+    fn new(data: &TypeMap) -> Result<Option<Self>, Error>;
+    /// Returns `None` if the platform isn't configured.
+    fn announcements(config: &Config) -> Option<Announcements>;
+    /// Accounts listed in the config file rather than linked via `/stream link`, keyed by Discord user.
+    fn config_accounts(_: &Config) -> BTreeMap<UserId, String> { BTreeMap::default() }
+    /// How long to wait between checks for live streams.
+    fn poll_interval(&self) -> Duration;
+    /// If the platform notifies Peter about streams, this is notified to check for live streams without waiting for the next poll.
+    fn wakeup(&self) -> Option<Arc<Notify>> { None }
+    /// Replaces the provider with one built from a reloaded config. Providers can override this to keep state which is still valid.
+    fn reconfigure(&mut self, new: Self) { *self = new }
+    /// Called with the current list of accounts before each check, e.g. to subscribe to notifications and unsubscribe from accounts which were removed.
+    fn prepare<'a>(&'a mut self, _: &'a [String]) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'a>> { Box::pin(future::ok(())) }
+    /// Returns the streams which are currently live on any of the given accounts.
+    fn live_streams<'a>(&'a self, accounts: &'a [String]) -> Pin<Box<dyn Future<Output = Result<Vec<LiveStream>, Error>> + Send + 'a>>;
+    /// Looks up an account by the name a user would enter, e.g. a login or handle. Returns `None` if there's no such account.
+    fn resolve<'a>(&'a self, name: &'a str) -> Pin<Box<dyn Future<Output = Result<Option<Account>, Error>> + Send + 'a>>;
+}
+
+fn announcement_embed<P: StreamProvider>(title: &str, url: &str, description: String) -> CreateEmbed {
+    CreateEmbed::default()
+        .color(P::COLOUR)
+        .title(title)
+        .url(url)
+        .description(description)
+}
+
+fn format_duration(duration: TimeDelta) -> String {
+    let hours = duration.num_hours();
+    let minutes = duration.num_minutes() % 60;
+    if hours > 0 { format!("{hours} Std. {minutes} Min.") } else { format!("{minutes} Min.") }
+}
+
+/// Treats a 404 response, e.g. because the announcement was deleted by hand, as success.
+fn ignore_not_found<T>(result: serenity::Result<T>) -> Result<(), Error> {
+    match result {
+        Ok(_) => Ok(()),
+        Err(serenity::Error::Http(HttpError::UnsuccessfulRequest(response))) if response.status_code == StatusCode::NOT_FOUND => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Returns the accounts of the members whose streams are announced, keyed by Discord user.
+///
+/// Accounts linked via `/stream link` take precedence over those in the config file, which are passed as `accounts`, and members who opted out of announcements are omitted.
+async fn accounts<P: StreamProvider>(pool: &PgPool, mut accounts: BTreeMap<UserId, String>) -> Result<BTreeMap<UserId, String>, Error> {
+    for row in sqlx::query!("SELECT discord_user, account, announce FROM stream_links WHERE platform = $1", P::PLATFORM).fetch_all(pool).await? {
+        let discord_user = UserId::new(row.discord_user as u64);
+        if row.announce {
+            accounts.insert(discord_user, row.account);
+        } else {
+            accounts.remove(&discord_user);
+        }
+    }
+    Ok(accounts)
+}
+
+/// Announces when a member starts streaming on the platform, keeps the announcement up to date while the stream is live, and marks it as ended afterwards.
+///
+/// Announcements are stored in the `stream_announcements` table, so a restart neither repeats announcements nor misses streams which started in the meantime.
+/// The provider is rebuilt from the config before each check, so changes from reloading the config take effect without a restart.
+/// While the platform isn't configured or the database is disabled, the config is checked again every [`UNCONFIGURED_INTERVAL`].
+pub async fn alerts<P: StreamProvider>(ctx_fut: RwFuture<Context>) -> Result<Never, Error> {
+    let mut provider = None::<P>;
+    loop {
+        let ctx = ctx_fut.read().await;
+        let (pool, new_provider, announcements, config_accounts) = {
+            let data = (*ctx).data.read().await;
+            let config = data.get::<Config>().ok_or(Error::MissingConfig)?;
+            (
+                data.get::<Database>().expect("missing database connection").clone(),
+                P::new(&data)?,
+                P::announcements(config),
+                P::config_accounts(config),
+            )
+        };
+        // without a database, there's nowhere to store announcements
+        let (Some(pool), Some(new_provider), Some(announcements)) = (pool, new_provider, announcements) else {
+            provider = None;
+            drop(ctx);
+            sleep(UNCONFIGURED_INTERVAL).await;
+            continue
+        };
+        let provider = match provider {
+            Some(ref mut provider) => {
+                provider.reconfigure(new_provider);
+                provider
+            }
+            None => provider.insert(new_provider),
+        };
+        let Announcements { channel, role, delete_after } = announcements;
+        let accounts = accounts::<P>(&pool, config_accounts).await?;
+        let discord_users = accounts.iter().map(|(discord_user, account)| (account.clone(), *discord_user)).collect::<HashMap<_, _>>();
+        let account_ids = accounts.into_values().collect_vec();
+        provider.prepare(&account_ids).await?;
+        // some platforms return arbitrary streams when not filtering by account, so they aren't asked at all if there are no accounts
+        let live = if account_ids.is_empty() { Vec::default() } else { provider.live_streams(&account_ids).await? };
+        for stream in &live {
+            let Some(user_id) = discord_users.get(&stream.account) else { continue };
+            let category = stream.category.clone().unwrap_or_default();
+            if let Some(row) = sqlx::query!("SELECT channel, message, title, url, game, ended_at, missed_checks FROM stream_announcements WHERE platform = $1 AND stream_id = $2", P::PLATFORM, stream.id).fetch_optional(&pool).await? {
+                if row.ended_at.is_some() {
+                    // the stream was only missing temporarily, so the announcement is restored instead of repeated
+                    if let Some(message) = row.message {
+                        ignore_not_found(ChannelId::new(row.channel as u64).edit_message(&*ctx, MessageId::new(message as u64), EditMessage::default()
+                            .content(MessageBuilder::default().mention(user_id).push(" streamt jetzt auf ").mention(&role).build())
+                            .embed(announcement_embed::<P>(&stream.title, &row.url, category.clone()))
+                        ).await)?;
+                    }
+                    sqlx::query!("UPDATE stream_announcements SET title = $1, game = $2, ended_at = NULL, delete_at = NULL, missed_checks = 0 WHERE platform = $3 AND stream_id = $4", stream.title, category, P::PLATFORM, stream.id).execute(&pool).await?;
+                } else if row.missed_checks > 0 || row.title != stream.title || row.game != category {
+                    if let Some(message) = row.message.filter(|_| row.title != stream.title || row.game != category) {
+                        ignore_not_found(ChannelId::new(row.channel as u64).edit_message(&*ctx, MessageId::new(message as u64), EditMessage::default()
+                            .embed(announcement_embed::<P>(&stream.title, &row.url, category.clone()))
+                        ).await)?;
+                    }
+                    sqlx::query!("UPDATE stream_announcements SET title = $1, game = $2, missed_checks = 0 WHERE platform = $3 AND stream_id = $4", stream.title, category, P::PLATFORM, stream.id).execute(&pool).await?;
+                }
+            } else {
+                // the announcement is reserved before it's sent, so it isn't repeated if saving it fails afterwards
+                sqlx::query!("INSERT INTO stream_announcements (platform, stream_id, discord_user, channel, title, url, game, started_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+                    P::PLATFORM,
+                    stream.id,
+                    user_id.get() as i64,
+                    channel.get() as i64,
+                    stream.title,
+                    stream.url,
+                    category,
+                    stream.started_at,
+                ).execute(&pool).await?;
+                let msg = match channel.send_message(&*ctx, CreateMessage::default()
+                    .content(MessageBuilder::default().mention(user_id).push(" streamt jetzt auf ").mention(&role).build())
+                    .add_embed(announcement_embed::<P>(&stream.title, &stream.url, category.clone()))
+                ).await {
+                    Ok(msg) => msg,
+                    Err(e) => {
+                        // nothing was announced, so the stream is retried on the next check
+                        sqlx::query!("DELETE FROM stream_announcements WHERE platform = $1 AND stream_id = $2", P::PLATFORM, stream.id).execute(&pool).await?;
+                        return Err(e.into())
+                    }
+                };
+                sqlx::query!("UPDATE stream_announcements SET message = $1 WHERE platform = $2 AND stream_id = $3", msg.id.get() as i64, P::PLATFORM, stream.id).execute(&pool).await?;
+            }
+        }
+        let live = live.into_iter().map(|stream| stream.id).collect_vec();
+        for row in sqlx::query!("SELECT stream_id, discord_user, channel, message, title, url, game, started_at, missed_checks FROM stream_announcements WHERE platform = $1 AND ended_at IS NULL AND NOT (stream_id = ANY($2))", P::PLATFORM, &live).fetch_all(&pool).await? {
+            if row.missed_checks + 1 < MISSED_CHECKS_UNTIL_ENDED {
+                sqlx::query!("UPDATE stream_announcements SET missed_checks = missed_checks + 1 WHERE platform = $1 AND stream_id = $2", P::PLATFORM, row.stream_id).execute(&pool).await?;
+                continue
+            }
+            let now = Utc::now();
+            let description = if row.game.is_empty() { String::default() } else { format!("{}\n\n", row.game) };
+            if let Some(message) = row.message {
+                ignore_not_found(ChannelId::new(row.channel as u64).edit_message(&*ctx, MessageId::new(message as u64), EditMessage::default()
+                    .content(MessageBuilder::default().mention(&UserId::new(row.discord_user as u64)).push(format!(" hat auf {} gestreamt", P::NAME)).build())
+                    .embed(announcement_embed::<P>(&row.title, &row.url, format!("{description}Stream beendet nach {}", format_duration(now - row.started_at))))
+                ).await)?;
+            }
+            sqlx::query!("UPDATE stream_announcements SET ended_at = $1, delete_at = $2, missed_checks = missed_checks + 1 WHERE platform = $3 AND stream_id = $4",
+                now,
+                delete_after.map(|delete_after| now + TimeDelta::minutes(delete_after.into())),
+                P::PLATFORM,
+                row.stream_id,
+            ).execute(&pool).await?;
+        }
+        for row in sqlx::query!("SELECT stream_id, channel, message FROM stream_announcements WHERE platform = $1 AND delete_at <= NOW()", P::PLATFORM).fetch_all(&pool).await? {
+            if let Some(message) = row.message {
+                ignore_not_found(ChannelId::new(row.channel as u64).delete_message(&*ctx, MessageId::new(message as u64)).await)?;
+            }
+            sqlx::query!("DELETE FROM stream_announcements WHERE platform = $1 AND stream_id = $2", P::PLATFORM, row.stream_id).execute(&pool).await?;
+        }
+        drop(ctx);
+        if let Some(wakeup) = provider.wakeup() {
+            // a notification from the platform ends the wait early
+            let _ = timeout(provider.poll_interval(), wakeup.notified()).await;
+        } else {
+            sleep(provider.poll_interval()).await;
+        }
+    }
+}
+
+/// Looks up the account on the platform and links the Discord user to it.
+///
+/// Returns the account's display name, or `None` if there is no such account.
+pub async fn link<P: StreamProvider>(ctx: &Context, user: UserId, name: &str) -> Result<Option<String>, Error> {
+    let (provider, pool) = {
+        let data = ctx.data.read().await;
+        (P::new(&data)?.ok_or(Error::PlatformNotConfigured(P::NAME))?, crate::database(&data)?)
+    };
+    let Some(account) = provider.resolve(name).await? else { return Ok(None) };
+    sqlx::query!("INSERT INTO stream_links (discord_user, platform, account) VALUES ($1, $2, $3) ON CONFLICT (discord_user, platform) DO UPDATE SET account = EXCLUDED.account",
+        user.get() as i64,
+        P::PLATFORM,
+        account.id,
+    ).execute(&pool).await?;
+    Ok(Some(account.display_name))
+}
+
+/// Removes the link between the Discord user and their account on the platform. Returns `false` if there was no link.
+///
+/// Accounts listed in the config file have to be removed from there by hand.
+pub async fn unlink<P: StreamProvider>(pool: &PgPool, user: UserId) -> Result<bool, Error> {
+    Ok(sqlx::query!("DELETE FROM stream_links WHERE discord_user = $1 AND platform = $2", user.get() as i64, P::PLATFORM).execute(pool).await?.rows_affected() > 0)
+}
+
+/// Sets whether the user's streams on the platform are announced. Returns `false` if the user has no linked account on the platform.
+pub async fn set_announce<P: StreamProvider>(ctx: &Context, user: UserId, announce: bool) -> Result<bool, Error> {
+    let (config_account, pool) = {
+        let data = ctx.data.read().await;
+        (
+            P::config_accounts(data.get::<Config>().ok_or(Error::MissingConfig)?).remove(&user),
+            crate::database(&data)?,
+        )
+    };
+    if sqlx::query!("UPDATE stream_links SET announce = $1 WHERE discord_user = $2 AND platform = $3", announce, user.get() as i64, P::PLATFORM).execute(&pool).await?.rows_affected() > 0 {
+        return Ok(true)
+    }
+    // accounts from the config file get a link so the choice is remembered
+    let Some(config_account) = config_account else { return Ok(false) };
+    sqlx::query!("INSERT INTO stream_links (discord_user, platform, account, announce) VALUES ($1, $2, $3, $4)", user.get() as i64, P::PLATFORM, config_account, announce).execute(&pool).await?;
+    Ok(true)
+}
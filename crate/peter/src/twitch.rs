@@ -1,100 +1,178 @@
+//! Announcing streams on Twitch.
+
 use {
     std::{
-        collections::BTreeMap,
-        convert::Infallible as Never,
+        collections::{
+            BTreeMap,
+            BTreeSet,
+        },
+        future::Future,
         iter,
-        pin::pin,
+        mem,
+        pin::{
+            Pin,
+            pin,
+        },
+        sync::Arc,
         time::Duration,
     },
     futures::prelude::*,
-    itertools::Itertools as _,
     serde::{
         Deserialize,
         Serialize,
     },
     serenity::{
-        all::{
-            CreateEmbed,
-            CreateMessage,
-        },
         model::prelude::*,
         prelude::*,
-        utils::MessageBuilder,
     },
-    serenity_utils::RwFuture,
-    tokio::time::sleep,
+    tokio::sync::Notify,
     twitch_helix::{
         Client,
-        model::Stream,
+        model::{
+            Stream,
+            User,
+        },
+    },
+    crate::{
+        Error,
+        stream::{
+            Account,
+            Announcements,
+            LiveStream,
+            StreamProvider,
+        },
     },
-    crate::Error,
 };
 
-const CHANNEL: ChannelId = ChannelId::new(668518137334857728);
-const ROLE: RoleId = RoleId::new(668534306515320833);
+pub mod eventsub;
 
-#[derive(Deserialize, Serialize)]
+/// How often streams are checked if EventSub isn't configured.
+const POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often streams are checked if EventSub is configured, in case a notification is missed.
+const FALLBACK_POLL_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Config {
     #[serde(rename = "clientID")]
     client_id: String,
-    client_secret: String,
+    /// Can be omitted if it's provided via the `PETER_TWITCH_CLIENT_SECRET` or `PETER_TWITCH_CLIENT_SECRET_FILE` environment variable.
+    #[serde(default)]
+    pub(crate) client_secret: String,
+    /// The channel where streams are announced.
+    #[serde(default = "default_channel")]
+    pub(crate) channel: ChannelId,
+    /// The role which is pinged when a stream is announced.
+    #[serde(default = "default_role")]
+    pub(crate) role: RoleId,
+    /// If set, announcements are deleted this many minutes after the stream ends.
+    #[serde(default)]
+    pub(crate) delete_after: Option<u32>,
+    /// If set, Twitch notifies Peter of streams going online or offline via webhook, and streams are only polled occasionally.
+    #[serde(default)]
+    pub(crate) event_sub: Option<eventsub::Config>,
+    /// Members can also link their accounts using `/stream link`, which takes precedence over this.
+    #[serde(default)]
     users: BTreeMap<UserId, twitch_helix::model::UserId>,
 }
 
-async fn client_and_users(ctx_fut: &RwFuture<Context>) -> Result<(Client<'static>, BTreeMap<UserId, twitch_helix::model::UserId>), Error> {
-    let ctx = ctx_fut.read().await;
-    let ctx_data = (*ctx).data.read().await;
-    let config = ctx_data.get::<crate::config::Config>().ok_or(Error::MissingConfig)?;
-    Ok((Client::new(
-        concat!("peter-discord/", env!("CARGO_PKG_VERSION")),
-        config.twitch.client_id.clone(),
-        twitch_helix::Credentials::from_client_secret(&config.twitch.client_secret, iter::empty::<String>()),
-    )?, config.twitch.users.clone()))
-}
+fn default_channel() -> ChannelId { ChannelId::new(668518137334857728) }
+fn default_role() -> RoleId { RoleId::new(668534306515320833) }
 
-async fn get_users(ctx_fut: &RwFuture<Context>) -> Result<BTreeMap<UserId, twitch_helix::model::UserId>, Error> {
-    let ctx = ctx_fut.read().await;
-    let ctx_data = (*ctx).data.read().await;
-    let config = ctx_data.get::<crate::config::Config>().ok_or(Error::MissingConfig)?;
-    Ok(config.twitch.users.clone())
+pub struct Twitch {
+    client: Client<'static>,
+    http: reqwest::Client,
+    config: Config,
+    /// The IDs of the EventSub subscriptions for each account, or `None` if they haven't been listed yet.
+    subscribed: Option<BTreeMap<String, Vec<String>>>,
+    wakeup: Arc<Notify>,
+    revoked: Arc<std::sync::Mutex<BTreeSet<String>>>,
 }
 
-/// Notifies #twitch when a Gefolge member starts streaming.
-pub async fn alerts(ctx_fut: RwFuture<Context>) -> Result<Never, Error> {
-    let (client, users) = client_and_users(&ctx_fut).await?;
-    let first_status = status(&client, users).await?;
-    let mut last_status = first_status.keys().cloned().collect::<Vec<_>>();
-    loop {
-        let users = get_users(&ctx_fut).await?;
-        let new_status = status(&client, users.clone()).await?;
-        for (user_id, stream) in &new_status {
-            if !last_status.iter().any(|iter_uid| user_id == iter_uid) {
-                let game = stream.game(&client).await?;
-                let ctx = ctx_fut.read().await;
-                CHANNEL.send_message(&*ctx, CreateMessage::default()
-                    .content(MessageBuilder::default().mention(user_id).push(" streamt jetzt auf ").mention(&ROLE).build())
-                    .add_embed(CreateEmbed::default()
-                        .color((0x77, 0x2c, 0xe8))
-                        .title(stream.to_string())
-                        .url(stream.url())
-                        .description(game.to_string())
-                    )
-                ).await?;
-            }
+impl StreamProvider for Twitch {
+    const PLATFORM: &'static str = "twitch";
+    const NAME: &'static str = "Twitch";
+    const COLOUR: (u8, u8, u8) = (0x77, 0x2c, 0xe8);
+
+    fn new(data: &TypeMap) -> Result<Option<Self>, Error> {
+        let config = data.get::<crate::config::Config>().ok_or(Error::MissingConfig)?.twitch.clone();
+        Ok(Some(Self {
+            client: Client::new(
+                concat!("peter-discord/", env!("CARGO_PKG_VERSION")),
+                config.client_id.clone(),
+                twitch_helix::Credentials::from_client_secret(&config.client_secret, iter::empty::<String>()),
+            )?,
+            http: reqwest::Client::builder()
+                .user_agent(concat!("peter-discord/", env!("CARGO_PKG_VERSION")))
+                .build()?,
+            subscribed: None,
+            wakeup: data.get::<eventsub::Wakeup>().expect("missing EventSub wakeup").clone(),
+            revoked: data.get::<eventsub::Revoked>().expect("missing revoked EventSub subscriptions").clone(),
+            config,
+        }))
+    }
+
+    fn announcements(config: &crate::config::Config) -> Option<Announcements> {
+        Some(Announcements {
+            channel: config.twitch.channel,
+            role: config.twitch.role,
+            delete_after: config.twitch.delete_after,
+        })
+    }
+
+    fn config_accounts(config: &crate::config::Config) -> BTreeMap<UserId, String> {
+        config.twitch.users.iter().map(|(&discord_user, twitch_user)| (discord_user, twitch_user.to_string())).collect()
+    }
+
+    fn poll_interval(&self) -> Duration {
+        if self.config.event_sub.is_some() { FALLBACK_POLL_INTERVAL } else { POLL_INTERVAL }
+    }
+
+    fn wakeup(&self) -> Option<Arc<Notify>> {
+        self.config.event_sub.is_some().then(|| self.wakeup.clone())
+    }
+
+    fn reconfigure(&mut self, mut new: Self) {
+        // the webhook endpoint keeps its address and secret until Peter is restarted, so subscriptions keep using the old settings as well
+        new.config.event_sub = self.config.event_sub.take();
+        new.subscribed = self.subscribed.take();
+        if new.config.client_id == self.config.client_id && new.config.client_secret == self.config.client_secret {
+            // keep the existing clients so their access token is reused instead of requesting a new one for every check
+            mem::swap(&mut new.client, &mut self.client);
+            mem::swap(&mut new.http, &mut self.http);
         }
-        last_status = new_status.keys().cloned().collect();
-        sleep(Duration::from_secs(60)).await;
+        *self = new;
+    }
+
+    fn prepare<'a>(&'a mut self, accounts: &'a [String]) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'a>> {
+        Box::pin(eventsub::sync_subscriptions(&self.http, &self.config, accounts, &mut self.subscribed, &self.revoked))
+    }
+
+    fn live_streams<'a>(&'a self, accounts: &'a [String]) -> Pin<Box<dyn Future<Output = Result<Vec<LiveStream>, Error>> + Send + 'a>> {
+        Box::pin(async move {
+            let mut live = Vec::default();
+            let mut streams = pin!(Stream::list(&self.client, None, Some(accounts.iter().cloned().map(twitch_helix::model::UserId::new).collect()), None));
+            while let Some(stream) = streams.try_next().await? {
+                live.push(LiveStream {
+                    account: stream.user_id.to_string(),
+                    id: stream.id.to_string(),
+                    title: stream.to_string(),
+                    category: Some(stream.game(&self.client).await?.to_string()),
+                    url: stream.url(),
+                    started_at: stream.started_at,
+                });
+            }
+            Ok(live)
+        })
     }
-}
 
-/// Returns the set of Gefolge members who are currently live on Twitch.
-async fn status(client: &Client<'_>, users: BTreeMap<UserId, twitch_helix::model::UserId>) -> Result<BTreeMap<UserId, Stream>, Error> {
-    let mut map = BTreeMap::default();
-    let mut stream_infos = pin!(Stream::list(client, None, Some(users.values().cloned().collect()), None));
-    while let Some(stream_info) = stream_infos.try_next().await? {
-        let discord_id = *users.iter().filter(|&(_, twitch_id)| stream_info.user_id == *twitch_id).exactly_one().map_err(|_| Error::TwitchUserLookup)?.0;
-        map.insert(discord_id, stream_info);
+    fn resolve<'a>(&'a self, name: &'a str) -> Pin<Box<dyn Future<Output = Result<Option<Account>, Error>> + Send + 'a>> {
+        Box::pin(async move {
+            Ok(pin!(User::list(&self.client, Vec::default(), vec![name.to_owned()])).try_next().await?.map(|user| Account {
+                id: user.id.to_string(),
+                display_name: user.display_name,
+            }))
+        })
     }
-    Ok(map)
 }
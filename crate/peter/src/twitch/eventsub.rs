@@ -0,0 +1,402 @@
+//! Receiving Twitch EventSub notifications via webhook, so streams are announced without waiting for the next poll.
+
+use {
+    std::{
+        collections::{
+            BTreeMap,
+            BTreeSet,
+            HashMap,
+        },
+        convert::Infallible as Never,
+        net::SocketAddr,
+        sync::{
+            Arc,
+            Mutex,
+        },
+        time::Duration,
+    },
+    axum::{
+        Router,
+        body::Bytes,
+        extract::State,
+        http::{
+            HeaderMap,
+            StatusCode,
+        },
+        response::{
+            IntoResponse as _,
+            Response,
+        },
+        routing::post,
+    },
+    chrono::{
+        TimeDelta,
+        prelude::*,
+    },
+    futures::future,
+    hmac::{
+        Hmac,
+        Mac as _,
+    },
+    serde::{
+        Deserialize,
+        Serialize,
+    },
+    serde_json::json,
+    serenity::prelude::*,
+    serenity_utils::RwFuture,
+    sha2::Sha256,
+    tokio::{
+        net::TcpListener,
+        sync::Notify,
+        time::sleep,
+    },
+    crate::Error,
+};
+
+/// Twitch retries notifications which weren't acknowledged, but messages older than this are rejected to prevent replay attacks.
+const MAX_MESSAGE_AGE_MINUTES: i64 = 10;
+
+/// The subscription types which wake up [`crate::stream::alerts`].
+const SUBSCRIPTION_TYPES: [&str; 2] = ["stream.online", "stream.offline"];
+
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Config {
+    /// The address on which the webhook endpoint listens.
+    address: SocketAddr,
+    /// The public URL under which Twitch can reach the endpoint, e.g. via a reverse proxy.
+    callback: String,
+    /// Shared secret for signing notifications, between 10 and 100 characters long.
+    secret: String,
+}
+
+/// Twitch sends `stream.online` before the stream shows up in the Helix API, so after a notification, streams are checked again after each of these delays.
+const FOLLOW_UP_DELAYS: [Duration; 2] = [Duration::from_secs(30), Duration::from_secs(120)];
+
+/// `typemap` key for waking up [`crate::stream::alerts`] when Twitch reports that a stream went online or offline.
+pub struct Wakeup;
+
+impl TypeMapKey for Wakeup {
+    type Value = Arc<Notify>;
+}
+
+/// `typemap` key for the Twitch users whose subscriptions were revoked, so they're recreated by the next [`sync_subscriptions`].
+pub struct Revoked;
+
+impl TypeMapKey for Revoked {
+    type Value = Arc<Mutex<BTreeSet<String>>>;
+}
+
+/// Checks the `Twitch-Eventsub-Message-Signature` header of an EventSub message.
+///
+/// See <https://dev.twitch.tv/docs/eventsub/handling-webhook-events/#verifying-the-event-message>.
+pub fn verify_signature(secret: &[u8], message_id: &str, timestamp: &str, body: &[u8], signature: &str) -> bool {
+    let Some(signature) = signature.strip_prefix("sha256=").and_then(|signature| hex::decode(signature).ok()) else { return false };
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(message_id.as_bytes());
+    mac.update(timestamp.as_bytes());
+    mac.update(body);
+    mac.verify_slice(&signature).is_ok()
+}
+
+/// Checks that the `Twitch-Eventsub-Message-Timestamp` header of an EventSub message is recent enough.
+pub fn is_recent(timestamp: &str, now: DateTime<Utc>) -> bool {
+    DateTime::parse_from_rfc3339(timestamp).is_ok_and(|timestamp| (now - timestamp.to_utc()).abs() <= TimeDelta::minutes(MAX_MESSAGE_AGE_MINUTES))
+}
+
+/// The IDs of recently received messages, so a replayed message is only handled once.
+#[derive(Default)]
+pub struct SeenMessages(HashMap<String, DateTime<Utc>>);
+
+impl SeenMessages {
+    /// Records the message ID. Returns `false` if it was already received.
+    pub fn insert(&mut self, message_id: &str, now: DateTime<Utc>) -> bool {
+        // messages are accepted if their timestamp is off by up to the maximum age in either direction, so IDs are remembered for twice that long
+        self.0.retain(|_, received| now - *received <= TimeDelta::minutes(2 * MAX_MESSAGE_AGE_MINUTES));
+        self.0.insert(message_id.to_owned(), now).is_none()
+    }
+}
+
+struct ServerState {
+    secret: String,
+    seen: Mutex<SeenMessages>,
+    wakeup: Arc<Notify>,
+    revoked: Arc<Mutex<BTreeSet<String>>>,
+}
+
+#[derive(Deserialize)]
+struct Challenge {
+    challenge: String,
+}
+
+#[derive(Deserialize)]
+struct Revocation {
+    subscription: Subscription,
+}
+
+/// Returns the Twitch user whose subscription was revoked, according to the body of a `revocation` message.
+pub fn revoked_user(body: &[u8]) -> Option<String> {
+    serde_json::from_slice::<Revocation>(body).ok()?.subscription.condition.broadcaster_user_id
+}
+
+async fn handle(State(state): State<Arc<ServerState>>, headers: HeaderMap, body: Bytes) -> Response {
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok()).unwrap_or_default();
+    let message_id = header("Twitch-Eventsub-Message-Id");
+    let timestamp = header("Twitch-Eventsub-Message-Timestamp");
+    let now = Utc::now();
+    if !verify_signature(state.secret.as_bytes(), message_id, timestamp, &body, header("Twitch-Eventsub-Message-Signature")) || !is_recent(timestamp, now) {
+        return StatusCode::FORBIDDEN.into_response()
+    }
+    if !state.seen.lock().expect("seen messages lock poisoned").insert(message_id, now) {
+        // Twitch may send a message more than once, duplicates are acknowledged without handling them again
+        return StatusCode::NO_CONTENT.into_response()
+    }
+    match header("Twitch-Eventsub-Message-Type") {
+        "webhook_callback_verification" => match serde_json::from_slice::<Challenge>(&body) {
+            Ok(Challenge { challenge }) => challenge.into_response(),
+            Err(_) => StatusCode::BAD_REQUEST.into_response(),
+        },
+        "notification" => {
+            state.wakeup.notify_one();
+            let wakeup = state.wakeup.clone();
+            tokio::spawn(async move {
+                for delay in FOLLOW_UP_DELAYS {
+                    sleep(delay).await;
+                    wakeup.notify_one();
+                }
+            });
+            StatusCode::NO_CONTENT.into_response()
+        }
+        "revocation" => {
+            eprintln!("Twitch revoked an EventSub subscription: {}", String::from_utf8_lossy(&body));
+            if let Some(user) = revoked_user(&body) {
+                state.revoked.lock().expect("revoked subscriptions lock poisoned").insert(user);
+            }
+            StatusCode::NO_CONTENT.into_response()
+        }
+        _ => StatusCode::BAD_REQUEST.into_response(),
+    }
+}
+
+/// Runs the webhook endpoint if it's configured. Otherwise, never returns, and streams are only found by polling.
+pub async fn serve(ctx_fut: RwFuture<Context>) -> Result<Never, Error> {
+    let server = {
+        let ctx = ctx_fut.read().await;
+        let data = (*ctx).data.read().await;
+        let config = data.get::<crate::config::Config>().ok_or(Error::MissingConfig)?;
+        config.twitch.event_sub.as_ref().map(|event_sub| (event_sub.address, ServerState {
+            secret: event_sub.secret.clone(),
+            seen: Mutex::default(),
+            wakeup: data.get::<Wakeup>().expect("missing EventSub wakeup").clone(),
+            revoked: data.get::<Revoked>().expect("missing revoked EventSub subscriptions").clone(),
+        }))
+    };
+    let Some((address, state)) = server else { return future::pending().await };
+    let router = Router::new()
+        .route("/twitch/eventsub", post(handle))
+        .with_state(Arc::new(state));
+    axum::serve(TcpListener::bind(address).await?, router).await?;
+    unreachable!("axum::serve only returns on error")
+}
+
+#[derive(Deserialize)]
+struct AppAccessToken {
+    access_token: String,
+}
+
+#[derive(Deserialize)]
+struct Subscriptions {
+    data: Vec<Subscription>,
+    #[serde(default)]
+    pagination: Pagination,
+}
+
+#[derive(Default, Deserialize)]
+struct Pagination {
+    cursor: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Subscription {
+    id: String,
+    status: String,
+    #[serde(rename = "type")]
+    kind: String,
+    condition: Condition,
+    transport: Transport,
+}
+
+#[derive(Deserialize)]
+struct Condition {
+    broadcaster_user_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Transport {
+    callback: Option<String>,
+}
+
+/// Returns the IDs of the existing subscriptions with Peter's callback URL, keyed by Twitch user.
+async fn list_subscriptions(http: &reqwest::Client, config: &super::Config, access_token: &str, callback: &str) -> Result<BTreeMap<String, Vec<String>>, Error> {
+    let mut subscriptions = BTreeMap::<_, Vec<_>>::default();
+    let mut cursor = None;
+    loop {
+        let mut request = http.get("https://api.twitch.tv/helix/eventsub/subscriptions")
+            .header("Client-Id", &config.client_id)
+            .bearer_auth(access_token);
+        if let Some(ref cursor) = cursor {
+            request = request.query(&[("after", cursor)]);
+        }
+        let Subscriptions { data, pagination } = request.send().await?.error_for_status()?.json().await?;
+        for subscription in data {
+            // subscriptions which failed or were revoked are recreated
+            if !matches!(&*subscription.status, "enabled" | "webhook_callback_verification_pending") { continue }
+            if !SUBSCRIPTION_TYPES.contains(&&*subscription.kind) || subscription.transport.callback.as_deref() != Some(callback) { continue }
+            let Some(user) = subscription.condition.broadcaster_user_id else { continue };
+            subscriptions.entry(user).or_default().push(subscription.id);
+        }
+        cursor = pagination.cursor;
+        if cursor.is_none() { break }
+    }
+    Ok(subscriptions)
+}
+
+/// Creates `stream.online` and `stream.offline` subscriptions for the given Twitch users and deletes those of users who are no longer announced, e.g. after `/stream unlink`.
+///
+/// `subscribed` maps Twitch users to their subscription IDs. If it's `None`, it's initialized with the subscriptions which exist from before a restart. Users in `revoked` are removed from it so their subscriptions are recreated.
+pub(super) async fn sync_subscriptions(http: &reqwest::Client, config: &super::Config, users: &[String], subscribed: &mut Option<BTreeMap<String, Vec<String>>>, revoked: &Mutex<BTreeSet<String>>) -> Result<(), Error> {
+    let Some(ref event_sub) = config.event_sub else { return Ok(()) };
+    if let Some(subscribed) = subscribed {
+        for user in revoked.lock().expect("revoked subscriptions lock poisoned").iter() {
+            subscribed.remove(user);
+        }
+    }
+    if subscribed.as_ref().is_some_and(|subscribed| subscribed.len() == users.len() && users.iter().all(|user| subscribed.contains_key(user))) { return Ok(()) }
+    let AppAccessToken { access_token } = http.post("https://id.twitch.tv/oauth2/token")
+        .form(&[("client_id", &*config.client_id), ("client_secret", &*config.client_secret), ("grant_type", "client_credentials")])
+        .send().await?
+        .error_for_status()?
+        .json().await?;
+    if subscribed.is_none() {
+        *subscribed = Some(list_subscriptions(http, config, &access_token, &event_sub.callback).await?);
+    }
+    let subscribed = subscribed.as_mut().expect("initialized above");
+    // the revoked subscriptions are recreated below, or were excluded when listing them
+    revoked.lock().expect("revoked subscriptions lock poisoned").clear();
+    // listed on the first conflict, see below
+    let mut existing = None;
+    let stale = subscribed.keys().filter(|&user| !users.contains(user)).cloned().collect::<Vec<_>>();
+    for user in stale {
+        for id in &subscribed[&user] {
+            let response = http.delete("https://api.twitch.tv/helix/eventsub/subscriptions")
+                .query(&[("id", id)])
+                .header("Client-Id", &config.client_id)
+                .bearer_auth(&access_token)
+                .send().await?;
+            // 404 Not Found means the subscription was already deleted, e.g. by Twitch
+            if response.status() != reqwest::StatusCode::NOT_FOUND {
+                response.error_for_status()?;
+            }
+        }
+        subscribed.remove(&user);
+    }
+    for user in users {
+        if subscribed.contains_key(user) { continue }
+        let mut ids = Vec::default();
+        let mut conflict = false;
+        for kind in SUBSCRIPTION_TYPES {
+            let response = http.post("https://api.twitch.tv/helix/eventsub/subscriptions")
+                .header("Client-Id", &config.client_id)
+                .bearer_auth(&access_token)
+                .json(&json!({
+                    "type": kind,
+                    "version": "1",
+                    "condition": { "broadcaster_user_id": user },
+                    "transport": {
+                        "method": "webhook",
+                        "callback": event_sub.callback,
+                        "secret": event_sub.secret,
+                    },
+                }))
+                .send().await?;
+            // 409 Conflict means the subscription already exists, e.g. if it was created by another instance
+            if response.status() == reqwest::StatusCode::CONFLICT {
+                conflict = true;
+            } else {
+                let Subscriptions { data, .. } = response.error_for_status()?.json().await?;
+                ids.extend(data.into_iter().map(|subscription| subscription.id));
+            }
+        }
+        if conflict {
+            // look up the IDs of the existing subscriptions so they can be deleted later
+            if existing.is_none() {
+                existing = Some(list_subscriptions(http, config, &access_token, &event_sub.callback).await?);
+            }
+            for id in existing.as_ref().expect("listed above").get(user).into_iter().flatten() {
+                if !ids.contains(id) {
+                    ids.push(id.clone());
+                }
+            }
+        }
+        subscribed.insert(user.clone(), ids);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &[u8] = b"0123456789abcdef";
+    const MESSAGE_ID: &str = "e76c6bd4-55c9-4987-8304-da1588d8988b";
+    const TIMESTAMP: &str = "2026-10-19T12:00:00.000000000Z";
+    const BODY: &[u8] = br#"{"subscription":{"type":"stream.online"}}"#;
+    const SIGNATURE: &str = "sha256=c606d4c5471c90a3688db8ebf5f92b753d1e1f114b6a232710f1b69c181892f6";
+
+    fn timestamp() -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(TIMESTAMP).unwrap().to_utc()
+    }
+
+    #[test]
+    fn valid_signature() {
+        assert!(verify_signature(SECRET, MESSAGE_ID, TIMESTAMP, BODY, SIGNATURE));
+    }
+
+    #[test]
+    fn tampered_body() {
+        assert!(!verify_signature(SECRET, MESSAGE_ID, TIMESTAMP, br#"{"subscription":{"type":"stream.offline"}}"#, SIGNATURE));
+    }
+
+    #[test]
+    fn wrong_secret() {
+        assert!(!verify_signature(b"fedcba9876543210", MESSAGE_ID, TIMESTAMP, BODY, SIGNATURE));
+    }
+
+    #[test]
+    fn malformed_signature() {
+        assert!(!verify_signature(SECRET, MESSAGE_ID, TIMESTAMP, BODY, &SIGNATURE["sha256=".len()..]));
+    }
+
+    #[test]
+    fn stale_timestamp() {
+        assert!(is_recent(TIMESTAMP, timestamp() + TimeDelta::minutes(MAX_MESSAGE_AGE_MINUTES)));
+        assert!(!is_recent(TIMESTAMP, timestamp() + TimeDelta::minutes(MAX_MESSAGE_AGE_MINUTES + 1)));
+        assert!(!is_recent("not a timestamp", timestamp()));
+    }
+
+    #[test]
+    fn revocation() {
+        let body = br#"{"subscription":{"id":"f1c2a387-161a-49f9-a165-0f21d7a4e1c4","status":"authorization_revoked","type":"stream.online","version":"1","condition":{"broadcaster_user_id":"12826"},"transport":{"method":"webhook","callback":"https://example.com/twitch/eventsub"},"created_at":"2026-10-19T12:00:00.000000000Z"}}"#;
+        assert_eq!(revoked_user(body).as_deref(), Some("12826"));
+        assert_eq!(revoked_user(b"{}"), None);
+    }
+
+    #[test]
+    fn replayed_message() {
+        let mut seen = SeenMessages::default();
+        assert!(seen.insert(MESSAGE_ID, timestamp()));
+        assert!(!seen.insert(MESSAGE_ID, timestamp() + TimeDelta::minutes(1)));
+        assert!(seen.insert("another message", timestamp() + TimeDelta::minutes(1)));
+    }
+}
@@ -1,12 +1,23 @@
-//! Helper functions for maintaining the guild member list on disk, which is used by gefolge.org to verify logins.
+//! Helper functions for maintaining the guild member list, which is used by gefolge.org to verify logins.
+//!
+//! The list can be exported to multiple [sinks](Sink), selected using the `userList` config key.
 
 use {
     std::{
+        collections::BTreeMap,
         convert::identity,
         future::Future,
+        path::PathBuf,
         pin::Pin,
+        sync::LazyLock,
     },
-    futures::future,
+    chrono::prelude::*,
+    itertools::Itertools as _,
+    serde::{
+        Deserialize,
+        Serialize,
+    },
+    serde_json::json,
     serenity::{
         model::prelude::*,
         prelude::*,
@@ -15,27 +26,43 @@ use {
         PgPool,
         types::Json,
     },
+    tokio::sync::Mutex,
+    wheel::fs,
     crate::{
-        Database,
-        GEFOLGE,
+        Error,
+        config::Config,
     },
 };
 
 /// Add a Discord account to the list of Gefolge guild members.
+///
+/// If the account is new or its nickname, roles, or username changed, an entry is added to the [`history`].
 pub async fn add(pool: &PgPool, member: &Member) -> sqlx::Result<()> {
     if member.user.bot { return Ok(()) }
+    let mut transaction = pool.begin().await?;
     let join_date = sqlx::query_scalar!(r#"SELECT joined FROM users WHERE snowflake = $1"#, member.user.id.get() as i64)
-        .fetch_optional(pool).await?
+        .fetch_optional(&mut *transaction).await?
         .and_then(identity);
+    sqlx::query!("INSERT INTO user_history (snowflake, nick, roles, username, member)
+        SELECT $1::INT8, $2::TEXT, $3::JSONB, $4::TEXT, TRUE
+        WHERE NOT EXISTS (SELECT 1 FROM users WHERE snowflake = $1 AND member AND nick IS NOT DISTINCT FROM $2 AND roles = $3 AND username = $4)
+    ",
+        member.user.id.get() as i64,
+        member.nick.as_ref().or(member.user.global_name.as_ref()),
+        Json(&member.roles) as _,
+        &member.user.name,
+    ).execute(&mut *transaction).await?;
     sqlx::query!("INSERT INTO users
-        (snowflake, discriminator, joined, nick, roles, username)
-        VALUES ($1, $2, $3, $4, $5, $6)
+        (snowflake, discriminator, joined, nick, roles, username, member, left_at)
+        VALUES ($1, $2, $3, $4, $5, $6, TRUE, NULL)
         ON CONFLICT (snowflake) DO UPDATE SET
         discriminator = EXCLUDED.discriminator,
         joined = EXCLUDED.joined,
         nick = EXCLUDED.nick,
         roles = EXCLUDED.roles,
-        username = EXCLUDED.username
+        username = EXCLUDED.username,
+        member = TRUE,
+        left_at = NULL
     ",
         member.user.id.get() as i64,
         member.user.discriminator.map(|discrim| discrim.get() as i16),
@@ -43,38 +70,367 @@ pub async fn add(pool: &PgPool, member: &Member) -> sqlx::Result<()> {
         member.nick.as_ref().or(member.user.global_name.as_ref()),
         Json(&member.roles) as _,
         &member.user.name,
-    ).execute(pool).await?;
+    ).execute(&mut *transaction).await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// Marks a Discord account as no longer being a member of the Gefolge guild.
+///
+/// Accounts without any gefolge.org user data are removed from the list entirely.
+pub async fn remove(pool: &PgPool, user: UserId) -> sqlx::Result<()> {
+    let mut transaction = pool.begin().await?;
+    sqlx::query!("INSERT INTO user_history (snowflake, nick, roles, username, member) SELECT snowflake, nick, roles, username, FALSE FROM users WHERE snowflake = $1 AND member", user.get() as i64).execute(&mut *transaction).await?;
+    sqlx::query!("DELETE FROM users WHERE snowflake = $1 AND data IS NULL", user.get() as i64).execute(&mut *transaction).await?;
+    sqlx::query!("UPDATE users SET member = FALSE, left_at = NOW() WHERE snowflake = $1 AND member", user.get() as i64).execute(&mut *transaction).await?;
+    transaction.commit().await?;
     Ok(())
 }
 
+/// The number of rows changed by [`replace_all`].
+pub struct Summary {
+    pub inserted: usize,
+    pub updated: usize,
+    /// Accounts which are no longer in the member list and were marked as non-members.
+    pub flagged: u64,
+    /// Accounts which are no longer in the member list and were deleted because they had no gefolge.org user data.
+    pub deleted: u64,
+}
+
+/// Replaces the list of Gefolge guild members with the given members in a single transaction.
+///
+/// Accounts which aren't in the given list are handled like in [`remove`].
+pub async fn replace_all(pool: &PgPool, members: &[&Member]) -> sqlx::Result<Summary> {
+    let members = members.iter().filter(|member| !member.user.bot).collect_vec();
+    let snowflakes = members.iter().map(|member| member.user.id.get() as i64).collect_vec();
+    let nicks = members.iter().map(|member| member.nick.clone().or_else(|| member.user.global_name.clone())).collect_vec();
+    let roles = members.iter().map(|member| serde_json::to_value(&member.roles).expect("failed to serialize role list")).collect_vec();
+    let usernames = members.iter().map(|member| member.user.name.clone()).collect_vec();
+    let mut transaction = pool.begin().await?;
+    sqlx::query!("INSERT INTO user_history (snowflake, nick, roles, username, member)
+        SELECT members.snowflake, members.nick, members.roles, members.username, TRUE
+        FROM UNNEST($1::INT8[], $2::TEXT[], $3::JSONB[], $4::TEXT[]) AS members (snowflake, nick, roles, username)
+        LEFT JOIN users ON users.snowflake = members.snowflake
+        WHERE users.snowflake IS NULL OR NOT users.member OR users.nick IS DISTINCT FROM members.nick OR users.roles <> members.roles OR users.username <> members.username
+    ", &snowflakes, &nicks, &roles, &usernames).execute(&mut *transaction).await?;
+    sqlx::query!("INSERT INTO user_history (snowflake, nick, roles, username, member)
+        SELECT snowflake, nick, roles, username, FALSE FROM users WHERE NOT (snowflake = ANY($1)) AND member
+    ", &snowflakes).execute(&mut *transaction).await?;
+    let inserted = sqlx::query_scalar!(r#"INSERT INTO users
+        (snowflake, discriminator, joined, nick, roles, username, member, left_at)
+        SELECT snowflake, discriminator, joined, nick, roles, username, TRUE, NULL::TIMESTAMPTZ
+        FROM UNNEST($1::INT8[], $2::INT2[], $3::TIMESTAMPTZ[], $4::TEXT[], $5::JSONB[], $6::TEXT[]) AS members (snowflake, discriminator, joined, nick, roles, username)
+        ON CONFLICT (snowflake) DO UPDATE SET
+        discriminator = EXCLUDED.discriminator,
+        joined = COALESCE(EXCLUDED.joined, users.joined),
+        nick = EXCLUDED.nick,
+        roles = EXCLUDED.roles,
+        username = EXCLUDED.username,
+        member = TRUE,
+        left_at = NULL
+        RETURNING (xmax = 0) AS "inserted!"
+    "#,
+        &snowflakes,
+        &members.iter().map(|member| member.user.discriminator.map(|discrim| discrim.get() as i16)).collect_vec(),
+        &members.iter().map(|member| member.joined_at.map(|joined_at| *joined_at)).collect_vec(),
+        &nicks,
+        &roles,
+        &usernames,
+    ).fetch_all(&mut *transaction).await?;
+    let deleted = sqlx::query!("DELETE FROM users WHERE NOT (snowflake = ANY($1)) AND data IS NULL", &snowflakes).execute(&mut *transaction).await?.rows_affected();
+    let flagged = sqlx::query!("UPDATE users SET member = FALSE, left_at = NOW() WHERE NOT (snowflake = ANY($1)) AND member", &snowflakes).execute(&mut *transaction).await?.rows_affected();
+    transaction.commit().await?;
+    let num_inserted = inserted.iter().filter(|&&inserted| inserted).count();
+    Ok(Summary {
+        inserted: num_inserted,
+        updated: inserted.len() - num_inserted,
+        flagged, deleted,
+    })
+}
+
+/// A snapshot of a Discord account's entry in the user list, recorded whenever it changes.
+pub struct HistoryEntry {
+    pub at: DateTime<Utc>,
+    pub nick: Option<String>,
+    pub roles: Vec<RoleId>,
+    pub username: String,
+    /// `false` if the account left the Gefolge guild at this point.
+    pub member: bool,
+}
+
+/// Returns the recorded changes to the given account's user list entry, oldest first.
+pub async fn history(pool: &PgPool, user: UserId) -> sqlx::Result<Vec<HistoryEntry>> {
+    Ok(sqlx::query!(r#"SELECT at, nick, roles AS "roles: Json<Vec<RoleId>>", username, member FROM user_history WHERE snowflake = $1 ORDER BY at, id"#, user.get() as i64)
+        .fetch_all(pool).await?
+        .into_iter()
+        .map(|row| HistoryEntry {
+            at: row.at,
+            nick: row.nick,
+            roles: row.roles.0,
+            username: row.username,
+            member: row.member,
+        })
+        .collect())
+}
+
+/// Returns the state of the user list entry at the given time, according to the history returned by [`history`].
+pub fn state_at(history: &[HistoryEntry], at: DateTime<Utc>) -> Option<&HistoryEntry> {
+    history.iter().take_while(|entry| entry.at <= at).last()
+}
+
+/// Returns since when the account has continuously had the given role while being a member, according to the history returned by [`history`].
+///
+/// Returns `None` if the account currently doesn't have the role.
+pub fn role_since(history: &[HistoryEntry], role: RoleId) -> Option<DateTime<Utc>> {
+    history.iter()
+        .rev()
+        .take_while(|entry| entry.member && entry.roles.contains(&role))
+        .last()
+        .map(|entry| entry.at)
+}
+
+/// Configures a place the list of Gefolge guild members is exported to.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum SinkConfig {
+    /// The `users` table in Peter's PostgreSQL database, which is used by gefolge.org.
+    Postgres,
+    /// A JSON file containing an array of members.
+    JsonFile(JsonFile),
+    /// A URL to which each change is sent as a JSON `POST` request.
+    Webhook(Webhook),
+}
+
+impl SinkConfig {
+    fn sink(&self) -> &dyn Sink {
+        match self {
+            Self::Postgres => &Postgres,
+            Self::JsonFile(sink) => sink,
+            Self::Webhook(sink) => sink,
+        }
+    }
+}
+
+/// A place the list of Gefolge guild members is exported to.
+pub trait Sink: Send + Sync {
+    /// Adds or updates a member.
+    fn upsert<'a>(&'a self, ctx: &'a Context, member: &'a Member) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'a>>;
+    /// Replaces the entire member list.
+    fn replace_all<'a>(&'a self, ctx: &'a Context, members: &'a [&'a Member]) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'a>>;
+    /// Handles a member leaving the guild.
+    fn remove<'a>(&'a self, ctx: &'a Context, user: UserId) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'a>>;
+}
+
+pub struct Postgres;
+
+impl Sink for Postgres {
+    fn upsert<'a>(&'a self, ctx: &'a Context, member: &'a Member) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'a>> {
+        Box::pin(async move {
+            let pool = crate::database(&*ctx.data.read().await)?;
+            add(&pool, member).await?;
+            Ok(())
+        })
+    }
+
+    fn replace_all<'a>(&'a self, ctx: &'a Context, members: &'a [&'a Member]) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'a>> {
+        Box::pin(async move {
+            let pool = crate::database(&*ctx.data.read().await)?;
+            let Summary { inserted, updated, flagged, deleted } = replace_all(&pool, members).await?;
+            println!("user list synced: {inserted} inserted, {updated} updated, {flagged} marked as non-members, {deleted} deleted");
+            Ok(())
+        })
+    }
+
+    fn remove<'a>(&'a self, ctx: &'a Context, user: UserId) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'a>> {
+        Box::pin(async move {
+            let pool = crate::database(&*ctx.data.read().await)?;
+            remove(&pool, user).await?;
+            Ok(())
+        })
+    }
+}
+
+/// How a member is represented in the JSON file and webhook payloads.
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ExportedMember {
+    snowflake: UserId,
+    username: String,
+    discriminator: Option<u16>,
+    nick: Option<String>,
+    roles: Vec<RoleId>,
+    joined: Option<DateTime<Utc>>,
+}
+
+impl From<&Member> for ExportedMember {
+    fn from(member: &Member) -> Self {
+        Self {
+            snowflake: member.user.id,
+            username: member.user.name.clone(),
+            discriminator: member.user.discriminator.map(|discrim| discrim.get()),
+            nick: member.nick.clone().or_else(|| member.user.global_name.clone()),
+            roles: member.roles.clone(),
+            joined: member.joined_at.map(|joined_at| *joined_at),
+        }
+    }
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct JsonFile {
+    path: PathBuf,
+}
+
+/// Serializes read-modify-write cycles on JSON file sinks, since member events can be handled concurrently.
+static JSON_FILE_LOCK: Mutex<()> = Mutex::const_new(());
+
+impl JsonFile {
+    async fn read(&self) -> Result<BTreeMap<UserId, ExportedMember>, Error> {
+        if !tokio::fs::try_exists(&self.path).await? { return Ok(BTreeMap::default()) }
+        let buf = fs::read_to_string(&self.path).await?;
+        Ok(serde_json::from_str::<Vec<ExportedMember>>(&buf)?.into_iter().map(|member| (member.snowflake, member)).collect())
+    }
+
+    /// Writes to a temporary file first and renames it over the export, so a crash can't leave a partially written file behind.
+    async fn write(&self, members: impl IntoIterator<Item = ExportedMember>) -> Result<(), Error> {
+        let buf = serde_json::to_vec_pretty(&members.into_iter().collect_vec())?;
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        fs::write(&tmp_path, buf).await?;
+        tokio::fs::rename(&tmp_path, &self.path).await?;
+        Ok(())
+    }
+}
+
+impl Sink for JsonFile {
+    fn upsert<'a>(&'a self, _: &'a Context, member: &'a Member) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'a>> {
+        Box::pin(async move {
+            if member.user.bot { return Ok(()) }
+            let _lock = JSON_FILE_LOCK.lock().await;
+            let mut members = self.read().await?;
+            members.insert(member.user.id, ExportedMember::from(member));
+            self.write(members.into_values()).await
+        })
+    }
+
+    fn replace_all<'a>(&'a self, _: &'a Context, members: &'a [&'a Member]) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'a>> {
+        Box::pin(async move {
+            let _lock = JSON_FILE_LOCK.lock().await;
+            self.write(members.iter().filter(|member| !member.user.bot).sorted_by_key(|member| member.user.id).map(|&member| ExportedMember::from(member))).await
+        })
+    }
+
+    fn remove<'a>(&'a self, _: &'a Context, user: UserId) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'a>> {
+        Box::pin(async move {
+            let _lock = JSON_FILE_LOCK.lock().await;
+            let mut members = self.read().await?;
+            if members.remove(&user).is_some() {
+                self.write(members.into_values()).await?;
+            }
+            Ok(())
+        })
+    }
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Webhook {
+    url: String,
+}
+
+/// Shared by all webhook sinks so connections can be reused.
+static HTTP_CLIENT: LazyLock<reqwest::Client> = LazyLock::new(reqwest::Client::default);
+
+impl Webhook {
+    async fn send(&self, payload: serde_json::Value) -> Result<(), Error> {
+        HTTP_CLIENT.post(&self.url)
+            .json(&payload)
+            .send().await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+impl Sink for Webhook {
+    fn upsert<'a>(&'a self, _: &'a Context, member: &'a Member) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'a>> {
+        Box::pin(async move {
+            if member.user.bot { return Ok(()) }
+            self.send(json!({
+                "type": "upsert",
+                "member": ExportedMember::from(member),
+            })).await
+        })
+    }
+
+    fn replace_all<'a>(&'a self, _: &'a Context, members: &'a [&'a Member]) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'a>> {
+        Box::pin(async move {
+            self.send(json!({
+                "type": "replaceAll",
+                "members": members.iter().filter(|member| !member.user.bot).map(|&member| ExportedMember::from(member)).collect_vec(),
+            })).await
+        })
+    }
+
+    fn remove<'a>(&'a self, _: &'a Context, user: UserId) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'a>> {
+        Box::pin(async move {
+            self.send(json!({
+                "type": "remove",
+                "snowflake": user,
+            })).await
+        })
+    }
+}
+
+/// Returns the configured sinks if the event is for the main guild.
+async fn sinks(ctx: &Context, guild: GuildId) -> Option<Vec<SinkConfig>> {
+    let data = ctx.data.read().await;
+    let config = data.get::<Config>().expect("missing config");
+    (guild == config.main_guild).then(|| config.user_list.clone())
+}
+
+/// Runs the given operation on all sinks, even if some of them fail. Returns the first error.
+async fn for_each_sink<'a>(sinks: &'a [SinkConfig], mut f: impl FnMut(&'a dyn Sink) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'a>>) -> Result<(), Error> {
+    let mut result = Ok(());
+    for sink in sinks {
+        if let Err(e) = f(sink.sink()).await {
+            if result.is_ok() { result = Err(e) }
+        }
+    }
+    result
+}
+
+/// Exports a member whose roles or nickname Peter changed itself, without waiting for the event from Discord.
+pub async fn upsert(ctx: &Context, member: &Member) -> Result<(), Error> {
+    let Some(sinks) = sinks(ctx, member.guild_id).await else { return Ok(()) };
+    for_each_sink(&sinks, |sink| sink.upsert(ctx, member)).await
+}
+
 pub enum Exporter {}
 
 impl serenity_utils::handler::user_list::ExporterMethods for Exporter {
     fn upsert<'a>(ctx: &'a Context, member: &'a Member) -> Pin<Box<dyn Future<Output = Result<(), Box<dyn std::error::Error + Send + Sync>>> + Send + 'a>> {
         Box::pin(async move {
-            if member.guild_id != GEFOLGE { return Ok(()) }
-            let data = ctx.data.read().await;
-            let pool = data.get::<Database>().expect("missing database connection");
-            add(pool, member).await?;
+            let Some(sinks) = sinks(ctx, member.guild_id).await else { return Ok(()) };
+            for_each_sink(&sinks, |sink| sink.upsert(ctx, member)).await?;
             Ok(())
         })
     }
 
     fn replace_all<'a>(ctx: &'a Context, members: Vec<&'a Member>) -> Pin<Box<dyn Future<Output = Result<(), Box<dyn std::error::Error + Send + Sync>>> + Send + 'a>> {
         Box::pin(async move {
-            let data = ctx.data.read().await;
-            let pool = data.get::<Database>().expect("missing database connection");
-            for member in members { //TODO parallel?
-                if member.guild_id == GEFOLGE {
-                    add(pool, member).await?;
-                }
-            }
+            let main_guild = ctx.data.read().await.get::<Config>().expect("missing config").main_guild;
+            let members = members.into_iter().filter(|member| member.guild_id == main_guild).collect_vec();
+            // an empty list most likely means this wasn't called for the main guild rather than everyone having left
+            if members.is_empty() { return Ok(()) }
+            let Some(sinks) = sinks(ctx, main_guild).await else { return Ok(()) };
+            for_each_sink(&sinks, |sink| sink.replace_all(ctx, &members)).await?;
             Ok(())
         })
     }
 
-    fn remove<'a>(_: &'a Context, _: UserId, _: GuildId) -> Pin<Box<dyn Future<Output = Result<(), Box<dyn std::error::Error + Send + Sync>>> + Send + 'a>> {
-        //TODO mark as non-member, remove entirely if no user data exists
-        Box::pin(future::ok(()))
+    fn remove<'a>(ctx: &'a Context, user: UserId, guild: GuildId) -> Pin<Box<dyn Future<Output = Result<(), Box<dyn std::error::Error + Send + Sync>>> + Send + 'a>> {
+        Box::pin(async move {
+            let Some(sinks) = sinks(ctx, guild).await else { return Ok(()) };
+            for_each_sink(&sinks, |sink| sink.remove(ctx, user)).await?;
+            Ok(())
+        })
     }
 }
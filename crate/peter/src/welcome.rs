@@ -0,0 +1,81 @@
+//! Greeting new members and notifying admins when someone joins.
+
+use {
+    serde::{
+        Deserialize,
+        Serialize,
+    },
+    serenity::{
+        all::{
+            CreateActionRow,
+            CreateButton,
+            CreateMessage,
+        },
+        http::StatusCode,
+        model::prelude::*,
+        prelude::*,
+        utils::MessageBuilder,
+    },
+    crate::{
+        Error,
+        roles,
+    },
+};
+
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Config {
+    /// The welcome message is posted in this channel.
+    #[serde(default)]
+    pub channel: Option<ChannelId>,
+    /// Whether the welcome message is also sent to the new member as a direct message.
+    #[serde(default)]
+    pub dm: bool,
+    /// The welcome message. `{user}` is replaced with a mention of the new member.
+    pub message: String,
+    /// Whether the new member is offered the self-assignable roles.
+    #[serde(default)]
+    pub roles: bool,
+}
+
+/// Sends the configured welcome messages for a member who just joined, and notifies the guild's admin channel.
+pub async fn greet(ctx: &Context, member: &Member) -> Result<(), Error> {
+    let (welcome, admin_channel) = {
+        let data = ctx.data.read().await;
+        let Some(guild_config) = data.get::<crate::config::Config>().ok_or(Error::MissingConfig)?.guilds.get(&member.guild_id) else { return Ok(()) };
+        (guild_config.welcome.clone(), guild_config.admin_channel)
+    };
+    if let Some(admin_channel) = admin_channel {
+        let created_at = member.user.id.created_at().unix_timestamp();
+        admin_channel.say(ctx, MessageBuilder::default()
+            .push("Neues Mitglied: ")
+            .mention(&member.user)
+            .push(format!(" (Account erstellt <t:{created_at}:D>, <t:{created_at}:R>)"))
+            .build()
+        ).await?;
+    }
+    let Some(welcome) = welcome else { return Ok(()) };
+    if member.user.bot { return Ok(()) }
+    let text = welcome.message.replace("{user}", &member.user.id.mention().to_string());
+    if let Some(channel) = welcome.channel {
+        let mut message = CreateMessage::default().content(&text);
+        if welcome.roles {
+            message = message.components(vec![CreateActionRow::Buttons(vec![CreateButton::new(roles::MENU_BUTTON).label("Rollen auswählen")])]);
+        }
+        channel.send_message(ctx, message).await?;
+    }
+    if welcome.dm {
+        let mut text = text;
+        if welcome.roles {
+            // buttons in direct messages don't know which guild they're for, so point to the slash command instead
+            text.push_str("\n\nMit `/iam` kannst du dir auf dem Server Rollen geben.");
+        }
+        match member.user.dm(ctx, CreateMessage::default().content(text)).await {
+            Ok(_) => {}
+            // the member doesn't accept direct messages from server members, so they only get the greeting in the channel, if any
+            Err(serenity::Error::Http(HttpError::UnsuccessfulRequest(response))) if response.status_code == StatusCode::FORBIDDEN => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(())
+}
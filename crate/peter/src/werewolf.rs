@@ -25,6 +25,7 @@ use {
     },
     itertools::Itertools as _,
     quantum_werewolf::game::{
+        Faction,
         NightAction,
         NightActionResult,
         Role,
@@ -36,7 +37,12 @@ use {
         Serialize,
     },
     serenity::{
-        all::EditMember,
+        all::{
+            CreateEmbed,
+            CreateEmbedFooter,
+            CreateMessage,
+            EditMember,
+        },
         model::prelude::*,
         prelude::*,
         utils::MessageBuilder,
@@ -58,6 +64,9 @@ pub struct Config {
     pub voice_channel: Option<ChannelId>,
 }
 
+/// The chances (in percent) of being in the village, being a werewolf, and being dead for a given role rank, or the faction of a dead player.
+type Probabilities = Result<[u8; 3], Faction>;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Vote {
     Player(UserId),
@@ -88,6 +97,8 @@ pub struct GameState {
     pub state: State<UserId>,
     alive: Option<HashSet<UserId>>,
     night_actions: Vec<NightAction<UserId>>,
+    /// The probability tables announced so far in the current game, one per day.
+    probability_history: Vec<Vec<Probabilities>>,
     timeouts: Vec<bool>,
     votes: HashMap<UserId, Vote>,
 }
@@ -99,6 +110,7 @@ impl GameState {
             state: State::default(),
             alive: None,
             night_actions: Vec::default(),
+            probability_history: Vec::default(),
             timeouts: Vec::default(),
             votes: HashMap::default(),
         }
@@ -198,21 +210,12 @@ impl GameState {
         Ok(result)
     }
 
-    async fn start_day(&self, ctx: &Context, day: &Day<UserId>) -> Result<(), Error> {
+    async fn start_day(&mut self, ctx: &Context, day: &Day<UserId>) -> Result<(), Error> {
         // announce probability table
-        let mut builder = MessageBuilder::default();
-        builder.push("Die aktuelle Wahrscheinlichkeitsverteilung:");
-        for (player_idx, probabilities) in day.probability_table().into_iter().enumerate() {
-            builder.push_line("").push_safe(match probabilities {
-                Ok((village_ratio, werewolves_ratio, dead_ratio)) => {
-                    format!("{}: {}% Dorf, {}% Werwolf, {}% tot", player_idx + 1, (village_ratio * 100.0).round() as u8, (werewolves_ratio * 100.0).round() as u8, (dead_ratio * 100.0).round() as u8)
-                }
-                Err(faction) => {
-                    format!("{}: tot (war {})", player_idx + 1, faction_name_sg(faction, Nom))
-                }
-            });
-        }
-        self.config.text_channel.say(ctx, builder.build()).await?;
+        self.probability_history.push(day.probability_table().into_iter().map(|probabilities| probabilities.map(|(village_ratio, werewolves_ratio, dead_ratio)|
+            [village_ratio, werewolves_ratio, dead_ratio].map(|ratio| (ratio * 100.0).round() as u8)
+        )).collect());
+        self.config.text_channel.send_message(ctx, CreateMessage::default().add_embed(probability_embed(&self.probability_history))).await?;
         // open discussion
         self.config.text_channel.create_permission(ctx, PermissionOverwrite {
             kind: PermissionOverwriteType::Role(self.config.role),
@@ -428,6 +431,7 @@ async fn handle_timeout(ctx: &Context, state_ref: &mut GameState) -> Result<Opti
                 if signups.num_players() > 4 && thread_rng().gen() { roles.push(Role::Healer); }
                 // start the game with that distribution
                 let started = signups.start(roles.clone())?;
+                state_ref.probability_history = Vec::default();
                 for (secret_id, player) in started.secret_ids().expect("failed to get secred player IDs").into_iter().enumerate() {
                     let dm = quantum_role_dm(&roles, started.num_players(), secret_id);
                     player.create_dm_channel(ctx).await?.say(ctx, &dm).await?;
@@ -530,6 +534,83 @@ pub async fn player_in_game(ctx: &Context, user_id: UserId, guild_id: GuildId) -
     state_ref.map_or(false, |state_ref| state_ref.state.secret_ids().map_or(false, |secret_ids| secret_ids.contains(&user_id)))
 }
 
+/// Renders the latest probability table, along with the change since the previous day and the history of each role rank.
+fn probability_embed(history: &[Vec<Probabilities>]) -> CreateEmbed {
+    const BAR_WIDTH: usize = 10;
+    const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    /// Discord's limit on the length of embed field values.
+    const MAX_FIELD_LEN: usize = 1024;
+
+    fn delta(current: u8, previous: Option<u8>) -> String {
+        match previous.map(|previous| i16::from(current) - i16::from(previous)) {
+            Some(0) => "  ±0".to_owned(),
+            Some(delta) => format!("{delta:>+4}"),
+            None => "    ".to_owned(),
+        }
+    }
+
+    fn spark(percent: u8) -> char {
+        SPARKS[(usize::from(percent) * (SPARKS.len() - 1) + 50) / 100]
+    }
+
+    let (current, earlier) = history.split_last().expect("no probability table to announce");
+    let previous = earlier.last();
+    let mut table = format!("```\n{:<6}{:<12}{:<11}{:<11}{}\n", "Rang", "Verteilung", "Dorf", "Werwolf", "tot");
+    for (rank, probabilities) in current.iter().enumerate() {
+        match *probabilities {
+            Ok(percentages @ [village, werewolves, _]) => {
+                let village_width = (usize::from(village) * BAR_WIDTH + 50) / 100;
+                let werewolves_width = ((usize::from(werewolves) * BAR_WIDTH + 50) / 100).min(BAR_WIDTH - village_width);
+                let bar = format!("{}{}{}", "█".repeat(village_width), "▓".repeat(werewolves_width), "░".repeat(BAR_WIDTH - village_width - werewolves_width));
+                table.push_str(&format!("{:>4}  {bar}  ", rank + 1));
+                for (idx, percentage) in percentages.into_iter().enumerate() {
+                    let previous = previous.and_then(|previous| previous.get(rank)).and_then(|previous| previous.as_ref().ok()).map(|previous| previous[idx]);
+                    table.push_str(&format!("{percentage:>3}% {}  ", delta(percentage, previous)));
+                }
+                table.truncate(table.trim_end().len());
+            }
+            Err(faction) => table.push_str(&format!("{:>4}  tot (war {})", rank + 1, faction_name_sg(faction, Nom))),
+        }
+        table.push('\n');
+    }
+    table.push_str("```");
+    let mut embed = CreateEmbed::default()
+        .title("Die aktuelle Wahrscheinlichkeitsverteilung")
+        .description(table)
+        .footer(CreateEmbedFooter::new("█ Dorf ▓ Werwolf ░ tot · Änderungen in Prozentpunkten seit dem Vortag"));
+    if !earlier.is_empty() {
+        // the history is split into multiple fields if there are too many players for one
+        let header = format!("{:<6}{:<12}{:<12}{}\n", "Rang", "Dorf", "Werwolf", "tot");
+        let mut fields = Vec::default();
+        let mut evolution = header.clone();
+        for rank in 0..current.len() {
+            let mut row = format!("{:>4}  ", rank + 1);
+            for idx in 0..3 {
+                let sparkline = history.iter()
+                    .rev().take(BAR_WIDTH).rev() // only show the last few days to stay within Discord's field length limit
+                    .map(|table| match table.get(rank) {
+                        Some(Ok(percentages)) => spark(percentages[idx]),
+                        Some(Err(_)) | None => '×',
+                    })
+                    .collect::<String>();
+                row.push_str(&format!("{sparkline:<12}"));
+            }
+            row.truncate(row.trim_end().len());
+            row.push('\n');
+            if "```\n".len() + evolution.chars().count() + row.chars().count() + "```".len() > MAX_FIELD_LEN {
+                fields.push(evolution);
+                evolution = header.clone();
+            }
+            evolution.push_str(&row);
+        }
+        fields.push(evolution);
+        for (idx, evolution) in fields.into_iter().enumerate() {
+            embed = embed.field(if idx == 0 { "Verlauf" } else { "Verlauf (Fortsetzung)" }, format!("```\n{evolution}```"), false);
+        }
+    }
+    embed
+}
+
 pub fn quantum_role_dm(roles: &[Role], num_players: usize, secret_id: usize) -> String {
     // Willkommen
     let mut builder = MessageBuilder::default();
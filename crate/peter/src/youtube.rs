@@ -0,0 +1,204 @@
+//! Announcing live streams on YouTube, using the YouTube Data API.
+
+use {
+    std::{
+        future::Future,
+        pin::Pin,
+        time::Duration,
+    },
+    chrono::prelude::*,
+    serde::{
+        Deserialize,
+        Serialize,
+        de::DeserializeOwned,
+    },
+    serenity::{
+        model::prelude::*,
+        prelude::*,
+    },
+    crate::{
+        Error,
+        stream::{
+            Account,
+            Announcements,
+            LiveStream,
+            StreamProvider,
+        },
+    },
+};
+
+/// How often streams are checked. Each check costs 2 units of the daily API quota per linked channel.
+const POLL_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// How many recent uploads of each channel are checked for live streams.
+const RECENT_UPLOADS: usize = 5;
+
+/// The maximum number of IDs per request to the `videos` endpoint.
+const MAX_VIDEO_IDS: usize = 50;
+
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Config {
+    /// Can be omitted if it's provided via the `PETER_YOUTUBE_API_KEY` or `PETER_YOUTUBE_API_KEY_FILE` environment variable.
+    #[serde(default)]
+    pub(crate) api_key: String,
+    /// The channel where streams are announced.
+    pub(crate) channel: ChannelId,
+    /// The role which is pinged when a stream is announced.
+    pub(crate) role: RoleId,
+    /// If set, announcements are deleted this many minutes after the stream ends.
+    #[serde(default)]
+    pub(crate) delete_after: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct ListResponse<T> {
+    #[serde(default)]
+    items: Vec<T>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PlaylistItem {
+    content_details: PlaylistItemContentDetails,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PlaylistItemContentDetails {
+    video_id: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Video {
+    id: String,
+    snippet: VideoSnippet,
+    live_streaming_details: Option<LiveStreamingDetails>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct VideoSnippet {
+    channel_id: String,
+    title: String,
+    live_broadcast_content: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LiveStreamingDetails {
+    actual_start_time: Option<DateTime<Utc>>,
+    actual_end_time: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize)]
+struct Channel {
+    id: String,
+    snippet: ChannelSnippet,
+}
+
+#[derive(Deserialize)]
+struct ChannelSnippet {
+    title: String,
+}
+
+pub struct YouTube {
+    http: reqwest::Client,
+    api_key: String,
+}
+
+/// Checks whether the name has the format of a channel ID, which is `UC` followed by 22 characters of URL-safe base64.
+fn is_channel_id(name: &str) -> bool {
+    name.len() == 24 && name.starts_with("UC") && name.bytes().all(|c| c.is_ascii_alphanumeric() || matches!(c, b'-' | b'_'))
+}
+
+impl YouTube {
+    async fn list<T: DeserializeOwned + Send>(&self, endpoint: &str, query: &[(&str, &str)]) -> Result<Vec<T>, Error> {
+        Ok(self.http.get(format!("https://www.googleapis.com/youtube/v3/{endpoint}"))
+            .query(query)
+            // sent as a header rather than a query parameter, since errors include the URL and end up in logs and error notifications
+            .header("X-Goog-Api-Key", &self.api_key)
+            .send().await?
+            .error_for_status()?
+            .json::<ListResponse<T>>().await?
+            .items)
+    }
+}
+
+impl StreamProvider for YouTube {
+    const PLATFORM: &'static str = "youtube";
+    const NAME: &'static str = "YouTube";
+    const COLOUR: (u8, u8, u8) = (0xff, 0x00, 0x00);
+
+    fn new(data: &TypeMap) -> Result<Option<Self>, Error> {
+        let Some(ref config) = data.get::<crate::config::Config>().ok_or(Error::MissingConfig)?.youtube else { return Ok(None) };
+        Ok(Some(Self {
+            http: reqwest::Client::builder()
+                .user_agent(concat!("peter-discord/", env!("CARGO_PKG_VERSION")))
+                .build()?,
+            api_key: config.api_key.clone(),
+        }))
+    }
+
+    fn announcements(config: &crate::config::Config) -> Option<Announcements> {
+        config.youtube.as_ref().map(|youtube| Announcements {
+            channel: youtube.channel,
+            role: youtube.role,
+            delete_after: youtube.delete_after,
+        })
+    }
+
+    fn poll_interval(&self) -> Duration { POLL_INTERVAL }
+
+    fn live_streams<'a>(&'a self, accounts: &'a [String]) -> Pin<Box<dyn Future<Output = Result<Vec<LiveStream>, Error>> + Send + 'a>> {
+        Box::pin(async move {
+            // the search endpoint can filter for live streams but costs 100 quota units, so the most recent uploads are checked instead
+            let mut video_ids = Vec::default();
+            for channel_id in accounts {
+                // a channel's uploads playlist has the channel ID with `UU` instead of `UC` as its ID
+                let Some(uploads) = channel_id.strip_prefix("UC").map(|suffix| format!("UU{suffix}")) else { continue };
+                match self.list::<PlaylistItem>("playlistItems", &[
+                    ("part", "contentDetails"),
+                    ("playlistId", &uploads),
+                    ("maxResults", &RECENT_UPLOADS.to_string()),
+                ]).await {
+                    Ok(items) => video_ids.extend(items.into_iter().map(|item| item.content_details.video_id)),
+                    // channels without any uploads don't have an uploads playlist
+                    Err(Error::Reqwest(e)) if e.status() == Some(reqwest::StatusCode::NOT_FOUND) => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            let mut live = Vec::default();
+            for chunk in video_ids.chunks(MAX_VIDEO_IDS) {
+                for video in self.list::<Video>("videos", &[
+                    ("part", "snippet,liveStreamingDetails"),
+                    ("id", &chunk.join(",")),
+                ]).await? {
+                    if video.snippet.live_broadcast_content != "live" { continue }
+                    let Some(LiveStreamingDetails { actual_start_time: Some(started_at), actual_end_time: None }) = video.live_streaming_details else { continue };
+                    live.push(LiveStream {
+                        account: video.snippet.channel_id,
+                        url: format!("https://www.youtube.com/watch?v={}", video.id),
+                        id: video.id,
+                        title: video.snippet.title,
+                        category: None,
+                        started_at,
+                    });
+                }
+            }
+            Ok(live)
+        })
+    }
+
+    fn resolve<'a>(&'a self, name: &'a str) -> Pin<Box<dyn Future<Output = Result<Option<Account>, Error>> + Send + 'a>> {
+        Box::pin(async move {
+            // accept both channel IDs and handles, with or without the leading @
+            let filter = if is_channel_id(name) { ("id", name.to_owned()) } else { ("forHandle", format!("@{}", name.trim_start_matches('@'))) };
+            Ok(self.list::<Channel>("channels", &[("part", "snippet"), (filter.0, &filter.1)]).await?.into_iter().next().map(|channel| Account {
+                id: channel.id,
+                display_name: channel.snippet.title,
+            }))
+        })
+    }
+}